@@ -7,7 +7,7 @@ use remotia::{
     render::winit::WinitRenderer,
 };
 use remotia_ffmpeg_codecs::{
-    decoders::DecoderBuilder,
+    decoders::{extractors::rgba::BGRAFrameExtractor, DecoderBuilder},
     encoders::{fillers::rgba::RGBAFrameFiller, EncoderBuilder},
     ffi,
     options::Options,
@@ -58,24 +58,20 @@ async fn main() {
             ScalerBuilder::new()
                 .input_width(width as i32)
                 .input_height(height as i32)
-                .input_pixel_format(ffi::AVPixelFormat_AV_PIX_FMT_RGBA)
-                .output_pixel_format(ffi::AVPixelFormat_AV_PIX_FMT_YUV420P)
-                .build(),
+                .input_pixel_format(ffi::AVPixelFormat_AV_PIX_FMT_RGBA),
         )
+        .pixel_format(ffi::AVPixelFormat_AV_PIX_FMT_YUV420P)
         .options(Options::new().set("crf", "26").set("tune", "zerolatency"))
-        .build();
+        .build()
+        .expect("Unable to build the encoder");
 
     let (decoder_pusher, decoder_puller) = DecoderBuilder::new()
         .codec_id("h264")
-        .scaler(
-            ScalerBuilder::new()
-                .input_width(width as i32)
-                .input_height(height as i32)
-                .input_pixel_format(ffi::AVPixelFormat_AV_PIX_FMT_YUV420P)
-                .output_pixel_format(ffi::AVPixelFormat_AV_PIX_FMT_BGRA)
-                .build(),
-        )
-        .build();
+        .extractor(BGRAFrameExtractor::new(DecodedRGBAFrameBuffer))
+        .output_pixel_format(ffi::AVPixelFormat_AV_PIX_FMT_BGRA)
+        .complete_frames(true)
+        .build()
+        .expect("Unable to build the decoder");
 
     let mut error_pipeline = Pipeline::<FrameData>::singleton(
         Component::new()
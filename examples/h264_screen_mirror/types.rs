@@ -1,10 +1,10 @@
 use std::collections::HashMap;
 
 use remotia::{
-    buffers::{BufMut, BytesMut},
+    buffers::BytesMut,
     traits::{BorrowFrameProperties, BorrowMutFrameProperties, FrameError, PullableFrameProperties},
 };
-use remotia_ffmpeg_codecs::FFMpegCodec;
+use remotia_ffmpeg_codecs::keyed::{KeyedCodecFrame, ReportedError};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum BufferType {
@@ -59,40 +59,28 @@ impl FrameError<Error> for FrameData {
     }
 }
 
-impl FFMpegCodec for FrameData {
-    fn write_packet_data(&mut self, packet_data: &[u8]) {
-        self.get_mut_ref(&BufferType::EncodedFrameBuffer)
-            .unwrap()
-            .put(packet_data)
-    }
-
-    fn get_packet_data_buffer(&self) -> &[u8] {
-        self.get_ref(&BufferType::EncodedFrameBuffer).unwrap()
-    }
-
-    fn write_decoded_buffer(&mut self, data: &[u8]) {
-        self.get_mut_ref(&BufferType::DecodedRGBAFrameBuffer)
-            .unwrap()
-            .put(data)
-    }
+impl KeyedCodecFrame for FrameData {
+    type Key = BufferType;
+    type Error = Error;
 
-    fn report_flush_error(&mut self) {
-        self.report_error(Error::FlushError)
-    }
-
-    fn report_codec_error(&mut self) {
-        self.report_error(Error::CodecError)
-    }
+    const PACKET_BUFFER_KEY: BufferType = BufferType::EncodedFrameBuffer;
 
-    fn report_decoder_drain_error(&mut self) {
-        self.report_error(Error::NoFrame)
+    fn frame_id(&self) -> i64 {
+        self.frame_id
     }
 
-    fn set_frame_id(&mut self, frame_id: i64) {
-        self.frame_id = frame_id;
+    fn frame_id_mut(&mut self) -> &mut i64 {
+        &mut self.frame_id
     }
 
-    fn get_frame_id(&self) -> i64 {
-        self.frame_id
+    fn map_error(error: ReportedError) -> Error {
+        match error {
+            ReportedError::Codec(report) => {
+                log::debug!("Codec error: {}", report);
+                Error::CodecError
+            }
+            ReportedError::EncoderFlushed => Error::FlushError,
+            ReportedError::DecoderDrained => Error::NoFrame,
+        }
     }
 }
@@ -0,0 +1,659 @@
+//! OBU handling for AV1 streams in the low overhead bitstream format, as produced by the encoders.
+
+use crate::error::CodecError;
+
+use super::{bits::BitReader, FrameSize};
+
+const SELECT_SCREEN_CONTENT_TOOLS: u32 = 2;
+const SELECT_INTEGER_MV: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObuType {
+    SequenceHeader,
+    TemporalDelimiter,
+    FrameHeader,
+    TileGroup,
+    Metadata,
+    /// Frame header followed by its tile group
+    Frame,
+    RedundantFrameHeader,
+    TileList,
+    Padding,
+    Reserved(u8),
+}
+
+impl ObuType {
+    pub(crate) fn from_raw(raw_type: u8) -> Self {
+        match raw_type {
+            1 => Self::SequenceHeader,
+            2 => Self::TemporalDelimiter,
+            3 => Self::FrameHeader,
+            4 => Self::TileGroup,
+            5 => Self::Metadata,
+            6 => Self::Frame,
+            7 => Self::RedundantFrameHeader,
+            8 => Self::TileList,
+            15 => Self::Padding,
+            raw_type => Self::Reserved(raw_type),
+        }
+    }
+
+    /// Whether the OBU starts with an uncompressed frame header.
+    pub fn has_frame_header(&self) -> bool {
+        matches!(self, Self::FrameHeader | Self::Frame | Self::RedundantFrameHeader)
+    }
+}
+
+/// An OBU borrowed from an encoded buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Obu<'a> {
+    data: &'a [u8],
+    payload_offset: usize,
+}
+
+impl<'a> Obu<'a> {
+    /// Header, optional extension and size field, and payload bytes.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        &self.data[self.payload_offset..]
+    }
+
+    /// The `obu_type` field of the header.
+    pub fn raw_type(&self) -> u8 {
+        (self.data[0] >> 3) & 0x0f
+    }
+
+    pub fn obu_type(&self) -> ObuType {
+        ObuType::from_raw(self.raw_type())
+    }
+
+    pub fn has_extension(&self) -> bool {
+        self.data[0] & 0x04 != 0
+    }
+
+    /// Temporal layer of the OBU, 0 without extension header.
+    pub fn temporal_id(&self) -> u8 {
+        if self.has_extension() {
+            self.data[1] >> 5
+        } else {
+            0
+        }
+    }
+
+    /// Spatial layer of the OBU, 0 without extension header.
+    pub fn spatial_id(&self) -> u8 {
+        if self.has_extension() {
+            (self.data[1] >> 3) & 0x03
+        } else {
+            0
+        }
+    }
+}
+
+/// Iterates over the OBUs of a temporal unit.
+///
+/// An OBU without size field extends to the end of the buffer.
+pub struct Obus<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> Obus<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { remaining: data }
+    }
+}
+
+impl<'a> Iterator for Obus<'a> {
+    type Item = Result<Obu<'a>, CodecError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining.is_empty() {
+            return None;
+        }
+
+        let result = next_obu(&mut self.remaining);
+        if result.is_err() {
+            self.remaining = &[];
+        }
+
+        Some(result)
+    }
+}
+
+/// Fields of an AV1 sequence header, for the first operating point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Av1SequenceHeader {
+    pub profile: u8,
+    pub still_picture: bool,
+    pub reduced_still_picture_header: bool,
+    pub level_idx: u8,
+    pub tier: bool,
+    pub bit_depth: u32,
+    pub max_frame_width: u32,
+    pub max_frame_height: u32,
+    frame_header_syntax: FrameHeaderSyntax,
+}
+
+/// Sequence level fields which the frame header syntax depends on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FrameHeaderSyntax {
+    decoder_model_info_present: bool,
+    equal_picture_interval: bool,
+    buffer_removal_time_length: u32,
+    frame_presentation_time_length: u32,
+    /// `operating_point_idc` and `decoder_model_present_for_this_op` of each operating point
+    operating_points: Vec<(u32, bool)>,
+    frame_width_bits: u32,
+    frame_height_bits: u32,
+    frame_id_length: Option<u32>,
+    force_screen_content_tools: u32,
+    force_integer_mv: u32,
+    order_hint_bits: u32,
+    enable_superres: bool,
+}
+
+impl Av1SequenceHeader {
+    pub fn parse(obu: &Obu) -> Result<Self, CodecError> {
+        if obu.obu_type() != ObuType::SequenceHeader {
+            return Err(CodecError::MalformedBitstream("unexpected OBU type"));
+        }
+
+        let mut reader = BitReader::new(obu.payload());
+
+        let profile = reader.read_bits(3)? as u8;
+        let still_picture = reader.read_bit()?;
+        let reduced_still_picture_header = reader.read_bit()?;
+
+        let mut decoder_model_info_present = false;
+        let mut equal_picture_interval = false;
+        let mut buffer_delay_length = 0;
+        let mut buffer_removal_time_length = 0;
+        let mut frame_presentation_time_length = 0;
+        let mut operating_points = Vec::new();
+
+        let (level_idx, tier) = if reduced_still_picture_header {
+            operating_points.push((0, false));
+            (reader.read_bits(5)? as u8, false)
+        } else {
+            let timing_info_present = reader.read_bit()?;
+            if timing_info_present {
+                reader.skip_bits(32 + 32)?; // num_units_in_display_tick, time_scale
+                equal_picture_interval = reader.read_bit()?;
+                if equal_picture_interval {
+                    reader.read_uvlc()?; // num_ticks_per_picture_minus_1
+                }
+
+                decoder_model_info_present = reader.read_bit()?;
+                if decoder_model_info_present {
+                    buffer_delay_length = reader.read_bits(5)? + 1;
+                    reader.skip_bits(32)?; // num_units_in_decoding_tick
+                    buffer_removal_time_length = reader.read_bits(5)? + 1;
+                    frame_presentation_time_length = reader.read_bits(5)? + 1;
+                }
+            }
+
+            let initial_display_delay_present = reader.read_bit()?;
+            let operating_points_count = reader.read_bits(5)? + 1;
+
+            let mut first_level = (0, false);
+            for index in 0..operating_points_count {
+                let operating_point_idc = reader.read_bits(12)?;
+                let level_idx = reader.read_bits(5)? as u8;
+                let tier = level_idx > 7 && reader.read_bit()?;
+                if index == 0 {
+                    first_level = (level_idx, tier);
+                }
+
+                let mut decoder_model_present = false;
+                if decoder_model_info_present {
+                    decoder_model_present = reader.read_bit()?;
+                    if decoder_model_present {
+                        // decoder_buffer_delay, encoder_buffer_delay, low_delay_mode_flag
+                        reader.skip_bits(2 * buffer_delay_length as usize + 1)?;
+                    }
+                }
+                if initial_display_delay_present && reader.read_bit()? {
+                    reader.skip_bits(4)?; // initial_display_delay_minus_1
+                }
+
+                operating_points.push((operating_point_idc, decoder_model_present));
+            }
+
+            first_level
+        };
+
+        let frame_width_bits = reader.read_bits(4)? + 1;
+        let frame_height_bits = reader.read_bits(4)? + 1;
+        let max_frame_width = reader.read_bits(frame_width_bits)? + 1;
+        let max_frame_height = reader.read_bits(frame_height_bits)? + 1;
+
+        let mut frame_id_length = None;
+        if !reduced_still_picture_header && reader.read_bit()? {
+            let delta_frame_id_length = reader.read_bits(4)? + 2;
+            let additional_frame_id_length = reader.read_bits(3)? + 1;
+            frame_id_length = Some(delta_frame_id_length + additional_frame_id_length);
+        }
+
+        // use_128x128_superblock, enable_filter_intra, enable_intra_edge_filter
+        reader.skip_bits(3)?;
+
+        let mut force_screen_content_tools = SELECT_SCREEN_CONTENT_TOOLS;
+        let mut force_integer_mv = SELECT_INTEGER_MV;
+        let mut order_hint_bits = 0;
+
+        if !reduced_still_picture_header {
+            // enable_interintra_compound, enable_masked_compound, enable_warped_motion, enable_dual_filter
+            reader.skip_bits(4)?;
+            let enable_order_hint = reader.read_bit()?;
+            if enable_order_hint {
+                reader.skip_bits(2)?; // enable_jnt_comp, enable_ref_frame_mvs
+            }
+
+            let choose_screen_content_tools = reader.read_bit()?;
+            if !choose_screen_content_tools {
+                force_screen_content_tools = reader.read_bits(1)?;
+            }
+            if force_screen_content_tools > 0 {
+                let choose_integer_mv = reader.read_bit()?;
+                if !choose_integer_mv {
+                    force_integer_mv = reader.read_bits(1)?;
+                }
+            }
+
+            if enable_order_hint {
+                order_hint_bits = reader.read_bits(3)? + 1;
+            }
+        }
+
+        let enable_superres = reader.read_bit()?;
+        reader.skip_bits(2)?; // enable_cdef, enable_restoration
+
+        let high_bitdepth = reader.read_bit()?;
+        let bit_depth = match (profile, high_bitdepth) {
+            (2, true) if reader.read_bit()? => 12,
+            (_, true) => 10,
+            (_, false) => 8,
+        };
+
+        Ok(Self {
+            profile,
+            still_picture,
+            reduced_still_picture_header,
+            level_idx,
+            tier,
+            bit_depth,
+            max_frame_width,
+            max_frame_height,
+            frame_header_syntax: FrameHeaderSyntax {
+                decoder_model_info_present,
+                equal_picture_interval,
+                buffer_removal_time_length,
+                frame_presentation_time_length,
+                operating_points,
+                frame_width_bits,
+                frame_height_bits,
+                frame_id_length,
+                force_screen_content_tools,
+                force_integer_mv,
+                order_hint_bits,
+                enable_superres,
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Av1FrameType {
+    Key,
+    Inter,
+    IntraOnly,
+    Switch,
+}
+
+/// Fields of an AV1 uncompressed frame header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Av1FrameHeader {
+    pub show_existing_frame: bool,
+    /// Unknown when the header shows an existing frame, since it only refers to it
+    pub frame_type: Option<Av1FrameType>,
+    pub show_frame: bool,
+    /// Upscaled frame size, only signalled by intra frames since the others take it from their references
+    pub size: Option<FrameSize>,
+    pub render_size: Option<FrameSize>,
+}
+
+impl Av1FrameHeader {
+    /// Parses the frame header at the start of a frame or frame header OBU, following the syntax set by the
+    /// sequence header of the stream.
+    pub fn parse(obu: &Obu, sequence_header: &Av1SequenceHeader) -> Result<Self, CodecError> {
+        if !obu.obu_type().has_frame_header() {
+            return Err(CodecError::MalformedBitstream("unexpected OBU type"));
+        }
+
+        let syntax = &sequence_header.frame_header_syntax;
+        let mut reader = BitReader::new(obu.payload());
+
+        let frame_type;
+        let show_frame;
+        let error_resilient_mode;
+
+        if sequence_header.reduced_still_picture_header {
+            frame_type = Av1FrameType::Key;
+            show_frame = true;
+            error_resilient_mode = true;
+        } else {
+            let show_existing_frame = reader.read_bit()?;
+            if show_existing_frame {
+                return Ok(Self {
+                    show_existing_frame,
+                    frame_type: None,
+                    show_frame: true,
+                    size: None,
+                    render_size: None,
+                });
+            }
+
+            frame_type = match reader.read_bits(2)? {
+                0 => Av1FrameType::Key,
+                1 => Av1FrameType::Inter,
+                2 => Av1FrameType::IntraOnly,
+                _ => Av1FrameType::Switch,
+            };
+
+            show_frame = reader.read_bit()?;
+            if show_frame && syntax.decoder_model_info_present && !syntax.equal_picture_interval {
+                // temporal_point_info
+                reader.skip_bits(syntax.frame_presentation_time_length as usize)?;
+            }
+            if !show_frame {
+                reader.skip_bits(1)?; // showable_frame
+            }
+
+            error_resilient_mode = frame_type == Av1FrameType::Switch
+                || (frame_type == Av1FrameType::Key && show_frame)
+                || reader.read_bit()?;
+        }
+
+        let frame_is_intra = matches!(frame_type, Av1FrameType::Key | Av1FrameType::IntraOnly);
+
+        reader.skip_bits(1)?; // disable_cdf_update
+
+        let allow_screen_content_tools = match syntax.force_screen_content_tools {
+            SELECT_SCREEN_CONTENT_TOOLS => reader.read_bit()?,
+            force_screen_content_tools => force_screen_content_tools == 1,
+        };
+        if allow_screen_content_tools && syntax.force_integer_mv == SELECT_INTEGER_MV {
+            reader.skip_bits(1)?; // force_integer_mv
+        }
+
+        if let Some(frame_id_length) = syntax.frame_id_length {
+            reader.skip_bits(frame_id_length as usize)?; // current_frame_id
+        }
+
+        let frame_size_override = match frame_type {
+            Av1FrameType::Switch => true,
+            _ if sequence_header.reduced_still_picture_header => false,
+            _ => reader.read_bit()?,
+        };
+
+        reader.skip_bits(syntax.order_hint_bits as usize)?; // order_hint
+        if !frame_is_intra && !error_resilient_mode {
+            reader.skip_bits(3)?; // primary_ref_frame
+        }
+
+        if syntax.decoder_model_info_present {
+            let buffer_removal_time_present = reader.read_bit()?;
+            if buffer_removal_time_present {
+                for &(operating_point_idc, decoder_model_present) in &syntax.operating_points {
+                    if !decoder_model_present {
+                        continue;
+                    }
+
+                    let in_temporal_layer = (operating_point_idc >> obu.temporal_id()) & 1 == 1;
+                    let in_spatial_layer = (operating_point_idc >> (obu.spatial_id() + 8)) & 1 == 1;
+                    if operating_point_idc == 0 || (in_temporal_layer && in_spatial_layer) {
+                        // buffer_removal_time
+                        reader.skip_bits(syntax.buffer_removal_time_length as usize)?;
+                    }
+                }
+            }
+        }
+
+        if !frame_is_intra {
+            return Ok(Self {
+                show_existing_frame: false,
+                frame_type: Some(frame_type),
+                show_frame,
+                size: None,
+                render_size: None,
+            });
+        }
+
+        let refresh_frame_flags = if frame_type == Av1FrameType::Key && show_frame {
+            0xff
+        } else {
+            reader.read_bits(8)?
+        };
+        if refresh_frame_flags != 0xff && error_resilient_mode {
+            reader.skip_bits(8 * syntax.order_hint_bits as usize)?; // ref_order_hint
+        }
+
+        let size = if frame_size_override {
+            FrameSize {
+                width: reader.read_bits(syntax.frame_width_bits)? + 1,
+                height: reader.read_bits(syntax.frame_height_bits)? + 1,
+            }
+        } else {
+            FrameSize {
+                width: sequence_header.max_frame_width,
+                height: sequence_header.max_frame_height,
+            }
+        };
+
+        // superres_params only affects the coded width, the size above is the upscaled one
+        if syntax.enable_superres && reader.read_bit()? {
+            reader.skip_bits(3)?; // coded_denom
+        }
+
+        let render_and_frame_size_different = reader.read_bit()?;
+        let render_size = if render_and_frame_size_different {
+            FrameSize {
+                width: reader.read_bits(16)? + 1,
+                height: reader.read_bits(16)? + 1,
+            }
+        } else {
+            size
+        };
+
+        Ok(Self {
+            show_existing_frame: false,
+            frame_type: Some(frame_type),
+            show_frame,
+            size: Some(size),
+            render_size: Some(render_size),
+        })
+    }
+
+    pub fn is_keyframe(&self) -> bool {
+        self.frame_type == Some(Av1FrameType::Key)
+    }
+}
+
+/// Splits the OBU at the start of `remaining` and moves past it.
+fn next_obu<'a>(remaining: &mut &'a [u8]) -> Result<Obu<'a>, CodecError> {
+    let data = *remaining;
+    let header = data[0];
+    if header & 0x80 != 0 {
+        return Err(CodecError::MalformedBitstream("OBU forbidden bit set"));
+    }
+
+    let has_extension = header & 0x04 != 0;
+    let has_size_field = header & 0x02 != 0;
+
+    let mut payload_offset = if has_extension { 2 } else { 1 };
+    if data.len() < payload_offset {
+        return Err(CodecError::MalformedBitstream("truncated OBU header"));
+    }
+
+    let payload_size = if has_size_field {
+        let (size, size_field_length) = read_leb128(&data[payload_offset..])?;
+        payload_offset += size_field_length;
+        size as usize
+    } else {
+        data.len() - payload_offset
+    };
+
+    let obu_size = payload_offset
+        .checked_add(payload_size)
+        .filter(|&obu_size| obu_size <= data.len())
+        .ok_or(CodecError::MalformedBitstream("truncated OBU"))?;
+
+    let (obu, rest) = data.split_at(obu_size);
+    *remaining = rest;
+
+    Ok(Obu {
+        data: obu,
+        payload_offset,
+    })
+}
+
+/// Reads a little-endian base 128 value, returning it along with the number of bytes it took.
+pub(crate) fn read_leb128(data: &[u8]) -> Result<(u64, usize), CodecError> {
+    let mut value = 0u64;
+    for (index, &byte) in data.iter().take(8).enumerate() {
+        value |= ((byte & 0x7f) as u64) << (index * 7);
+        if byte & 0x80 == 0 {
+            return Ok((value, index + 1));
+        }
+    }
+
+    Err(CodecError::MalformedBitstream("invalid OBU size field"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPORAL_DELIMITER: &[u8] = &[0x12, 0x00];
+
+    // Main profile, level 4.0, 1920x1080, with order hints on 7 bits and screen content tools left to each frame
+    const SEQUENCE_HEADER: &[u8] = &[
+        0x0a, 0x0b, 0x00, 0x00, 0x00, 0x42, 0xab, 0xbf, 0xc3, 0x73, 0xff, 0xe6, 0x01,
+    ];
+
+    // Shown keyframe at the maximum frame size
+    const KEYFRAME_HEADER: &[u8] = &[0x1a, 0x02, 0x10, 0x01];
+
+    // Shown inter frame, with order hint 1
+    const INTER_FRAME_HEADER: &[u8] = &[0x1a, 0x03, 0x30, 0x03, 0xe0];
+
+    fn single_obu(data: &[u8]) -> Obu<'_> {
+        let mut obus = Obus::new(data);
+        let obu = obus.next().unwrap().unwrap();
+        assert!(obus.next().is_none());
+        obu
+    }
+
+    fn sequence_header() -> Av1SequenceHeader {
+        Av1SequenceHeader::parse(&single_obu(SEQUENCE_HEADER)).unwrap()
+    }
+
+    #[test]
+    fn splits_obus() {
+        let temporal_unit = [TEMPORAL_DELIMITER, SEQUENCE_HEADER, KEYFRAME_HEADER].concat();
+        let obu_types: Vec<ObuType> = Obus::new(&temporal_unit)
+            .map(|obu| obu.unwrap().obu_type())
+            .collect();
+
+        assert_eq!(
+            obu_types,
+            [
+                ObuType::TemporalDelimiter,
+                ObuType::SequenceHeader,
+                ObuType::FrameHeader
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_obu_extension_and_unsized_obus() {
+        // Tile group with an extension header for temporal layer 2 and spatial layer 1, and no size field
+        let obu = single_obu(&[0x24, 0x48, 0xaa, 0xbb]);
+
+        assert_eq!(obu.obu_type(), ObuType::TileGroup);
+        assert_eq!((obu.temporal_id(), obu.spatial_id()), (2, 1));
+        assert_eq!(obu.payload(), [0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn rejects_malformed_obus() {
+        let truncated = &SEQUENCE_HEADER[..SEQUENCE_HEADER.len() - 1];
+        assert!(Obus::new(truncated).next().unwrap().is_err());
+
+        let forbidden_bit = [0x92, 0x00];
+        let mut obus = Obus::new(&forbidden_bit);
+        assert!(obus.next().unwrap().is_err());
+        assert!(obus.next().is_none());
+
+        assert!(read_leb128(&[0x80; 8]).is_err());
+        assert_eq!(read_leb128(&[0xe5, 0x8e, 0x26]).unwrap(), (624_485, 3));
+    }
+
+    #[test]
+    fn parses_sequence_header() {
+        let sequence_header = sequence_header();
+
+        assert_eq!(sequence_header.profile, 0);
+        assert!(!sequence_header.still_picture);
+        assert!(!sequence_header.reduced_still_picture_header);
+        assert_eq!((sequence_header.level_idx, sequence_header.tier), (8, false));
+        assert_eq!(sequence_header.bit_depth, 8);
+        assert_eq!(
+            (sequence_header.max_frame_width, sequence_header.max_frame_height),
+            (1920, 1080)
+        );
+        assert_eq!(sequence_header.frame_header_syntax.order_hint_bits, 7);
+        assert_eq!(
+            sequence_header
+                .frame_header_syntax
+                .force_screen_content_tools,
+            SELECT_SCREEN_CONTENT_TOOLS
+        );
+    }
+
+    #[test]
+    fn parses_frame_headers() {
+        let sequence_header = sequence_header();
+
+        let keyframe_header = Av1FrameHeader::parse(&single_obu(KEYFRAME_HEADER), &sequence_header).unwrap();
+        let frame_size = FrameSize {
+            width: 1920,
+            height: 1080,
+        };
+        assert_eq!(
+            keyframe_header,
+            Av1FrameHeader {
+                show_existing_frame: false,
+                frame_type: Some(Av1FrameType::Key),
+                show_frame: true,
+                size: Some(frame_size),
+                render_size: Some(frame_size),
+            }
+        );
+        assert!(keyframe_header.is_keyframe());
+
+        let inter_frame_header = Av1FrameHeader::parse(&single_obu(INTER_FRAME_HEADER), &sequence_header).unwrap();
+        assert_eq!(inter_frame_header.frame_type, Some(Av1FrameType::Inter));
+        assert_eq!(inter_frame_header.size, None);
+        assert!(!inter_frame_header.is_keyframe());
+
+        let show_existing_frame = Av1FrameHeader::parse(&single_obu(&[0x1a, 0x01, 0x80]), &sequence_header).unwrap();
+        assert!(show_existing_frame.show_existing_frame);
+        assert_eq!(show_existing_frame.frame_type, None);
+
+        assert!(Av1FrameHeader::parse(&single_obu(SEQUENCE_HEADER), &sequence_header).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use crate::error::CodecError;
+
+const END_OF_DATA: CodecError = CodecError::MalformedBitstream("unexpected end of data");
+
+/// Reads big-endian bit fields and exp-Golomb codes out of a raw byte sequence payload.
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn read_bit(&mut self) -> Result<bool, CodecError> {
+        let byte = self.data.get(self.position / 8).ok_or(END_OF_DATA)?;
+        let bit = (byte >> (7 - self.position % 8)) & 1;
+        self.position += 1;
+        Ok(bit == 1)
+    }
+
+    /// Reads up to 32 bits, most significant first.
+    pub fn read_bits(&mut self, count: u32) -> Result<u32, CodecError> {
+        debug_assert!(count <= 32);
+
+        let mut value = 0u64;
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+
+        Ok(value as u32)
+    }
+
+    pub fn skip_bits(&mut self, count: usize) -> Result<(), CodecError> {
+        if self.position + count > self.data.len() * 8 {
+            return Err(END_OF_DATA);
+        }
+
+        self.position += count;
+        Ok(())
+    }
+
+    /// Reads an unsigned exp-Golomb code, `ue(v)` in the H.264 and HEVC syntax tables.
+    pub fn read_ue(&mut self) -> Result<u32, CodecError> {
+        let mut leading_zeros = 0;
+        while !self.read_bit()? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return Err(CodecError::MalformedBitstream("exp-Golomb code too long"));
+            }
+        }
+
+        if leading_zeros == 0 {
+            return Ok(0);
+        }
+
+        Ok((1u32 << leading_zeros) - 1 + self.read_bits(leading_zeros)?)
+    }
+
+    /// Reads a variable length unsigned code, `uvlc()` in the AV1 syntax tables.
+    pub fn read_uvlc(&mut self) -> Result<u32, CodecError> {
+        let mut leading_zeros = 0;
+        while !self.read_bit()? {
+            leading_zeros += 1;
+        }
+
+        if leading_zeros >= 32 {
+            return Ok(u32::MAX);
+        }
+
+        Ok(((1u64 << leading_zeros) - 1 + self.read_bits(leading_zeros)? as u64) as u32)
+    }
+
+    /// Reads a signed exp-Golomb code, `se(v)` in the H.264 and HEVC syntax tables.
+    pub fn read_se(&mut self) -> Result<i32, CodecError> {
+        let code = self.read_ue()? as i64;
+        let value = if code % 2 == 1 { (code + 1) / 2 } else { -(code / 2) };
+        Ok(value as i32)
+    }
+}
@@ -0,0 +1,396 @@
+//! NAL unit handling for H.264 and HEVC elementary streams.
+
+use std::borrow::Cow;
+
+use remotia::buffers::BufMut;
+
+use crate::error::CodecError;
+
+mod parameter_sets;
+
+pub use parameter_sets::*;
+
+const ANNEX_B_START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NalCodec {
+    H264,
+    Hevc,
+}
+
+impl NalCodec {
+    /// Size of the NAL unit header, which precedes the payload.
+    pub fn header_size(&self) -> usize {
+        match self {
+            Self::H264 => 1,
+            Self::Hevc => 2,
+        }
+    }
+}
+
+/// Codec independent classification of NAL units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NalType {
+    /// Slice of a picture which the decoder can start from: IDR for H.264, IDR, CRA and BLA for HEVC
+    RandomAccess,
+    /// Slice of any other picture
+    Slice,
+    Vps,
+    Sps,
+    Pps,
+    Sei,
+    AccessUnitDelimiter,
+    Other,
+}
+
+/// A NAL unit borrowed from an encoded buffer, without start code or length prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NalUnit<'a> {
+    codec: NalCodec,
+    data: &'a [u8],
+}
+
+impl<'a> NalUnit<'a> {
+    pub fn new(codec: NalCodec, data: &'a [u8]) -> Self {
+        Self { codec, data }
+    }
+
+    pub fn codec(&self) -> NalCodec {
+        self.codec
+    }
+
+    /// Header and payload bytes, emulation prevention included.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The `nal_unit_type` field of the header.
+    pub fn raw_type(&self) -> u8 {
+        let first_byte = self.data.first().copied().unwrap_or(0);
+        match self.codec {
+            NalCodec::H264 => first_byte & 0x1f,
+            NalCodec::Hevc => (first_byte >> 1) & 0x3f,
+        }
+    }
+
+    pub fn nal_type(&self) -> NalType {
+        match (self.codec, self.raw_type()) {
+            (NalCodec::H264, 5) => NalType::RandomAccess,
+            (NalCodec::H264, 1..=4) => NalType::Slice,
+            (NalCodec::H264, 6) => NalType::Sei,
+            (NalCodec::H264, 7) => NalType::Sps,
+            (NalCodec::H264, 8) => NalType::Pps,
+            (NalCodec::H264, 9) => NalType::AccessUnitDelimiter,
+            (NalCodec::Hevc, 16..=23) => NalType::RandomAccess,
+            (NalCodec::Hevc, 0..=9) => NalType::Slice,
+            (NalCodec::Hevc, 32) => NalType::Vps,
+            (NalCodec::Hevc, 33) => NalType::Sps,
+            (NalCodec::Hevc, 34) => NalType::Pps,
+            (NalCodec::Hevc, 35) => NalType::AccessUnitDelimiter,
+            (NalCodec::Hevc, 39 | 40) => NalType::Sei,
+            _ => NalType::Other,
+        }
+    }
+
+    pub fn is_slice(&self) -> bool {
+        matches!(self.nal_type(), NalType::RandomAccess | NalType::Slice)
+    }
+
+    pub fn is_parameter_set(&self) -> bool {
+        matches!(self.nal_type(), NalType::Vps | NalType::Sps | NalType::Pps)
+    }
+
+    /// Payload following the header, with emulation prevention bytes removed. Borrowed when there were none.
+    pub fn rbsp(&self) -> Cow<'a, [u8]> {
+        let header_size = self.codec.header_size().min(self.data.len());
+        remove_emulation_prevention(&self.data[header_size..])
+    }
+}
+
+/// Removes the `0x03` bytes inserted after every pair of zero bytes to prevent start code emulation.
+///
+/// The data is only copied when it actually holds emulation prevention bytes.
+pub fn remove_emulation_prevention(data: &[u8]) -> Cow<'_, [u8]> {
+    let mut unescaped: Option<Vec<u8>> = None;
+    let mut zeros = 0;
+
+    for (index, &byte) in data.iter().enumerate() {
+        if zeros >= 2 && byte == 0x03 {
+            unescaped.get_or_insert_with(|| data[..index].to_vec());
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        if let Some(unescaped) = &mut unescaped {
+            unescaped.push(byte);
+        }
+    }
+
+    match unescaped {
+        Some(unescaped) => Cow::Owned(unescaped),
+        None => Cow::Borrowed(data),
+    }
+}
+
+/// Iterates over the NAL units of an Annex B buffer, where each unit follows a 3 or 4 bytes start code.
+///
+/// Bytes preceding the first start code are skipped.
+pub struct AnnexBNalUnits<'a> {
+    codec: NalCodec,
+    remaining: &'a [u8],
+}
+
+impl<'a> AnnexBNalUnits<'a> {
+    pub fn new(codec: NalCodec, data: &'a [u8]) -> Self {
+        let remaining = match find_start_code(data) {
+            Some(position) => &data[position + 3..],
+            None => &[],
+        };
+
+        Self { codec, remaining }
+    }
+}
+
+impl<'a> Iterator for AnnexBNalUnits<'a> {
+    type Item = NalUnit<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        next_annex_b_unit(&mut self.remaining).map(|data| NalUnit::new(self.codec, data))
+    }
+}
+
+/// Iterates over the NAL units of a length-prefixed (AVCC or HVCC) buffer, where each unit follows its big-endian
+/// size.
+pub struct LengthPrefixedNalUnits<'a> {
+    codec: NalCodec,
+    remaining: &'a [u8],
+    length_size: usize,
+}
+
+impl<'a> LengthPrefixedNalUnits<'a> {
+    /// `length_size` is the size in bytes of the prefixes, usually 4, as stated by the avcC or hvcC record.
+    pub fn new(codec: NalCodec, data: &'a [u8], length_size: usize) -> Result<Self, CodecError> {
+        check_length_size(length_size)?;
+
+        Ok(Self {
+            codec,
+            remaining: data,
+            length_size,
+        })
+    }
+}
+
+impl<'a> Iterator for LengthPrefixedNalUnits<'a> {
+    type Item = Result<NalUnit<'a>, CodecError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        next_length_prefixed_unit(&mut self.remaining, self.length_size)
+            .map(|result| result.map(|data| NalUnit::new(self.codec, data)))
+    }
+}
+
+/// Rewrites an Annex B buffer with length prefixes of `length_size` bytes instead of start codes.
+pub fn annex_b_to_length_prefixed(data: &[u8], length_size: usize, output: &mut impl BufMut) -> Result<(), CodecError> {
+    check_length_size(length_size)?;
+
+    let mut remaining = match find_start_code(data) {
+        Some(position) => &data[position + 3..],
+        None => return Ok(()),
+    };
+
+    while let Some(unit) = next_annex_b_unit(&mut remaining) {
+        if length_size < 4 && unit.len() >= 1 << (length_size * 8) {
+            return Err(CodecError::MalformedBitstream("NAL unit too large for the length size"));
+        }
+
+        output.put_uint(unit.len() as u64, length_size);
+        output.put_slice(unit);
+    }
+
+    Ok(())
+}
+
+/// Rewrites a buffer with length prefixes of `length_size` bytes as Annex B, with 4 bytes start codes.
+pub fn length_prefixed_to_annex_b(data: &[u8], length_size: usize, output: &mut impl BufMut) -> Result<(), CodecError> {
+    check_length_size(length_size)?;
+
+    let mut remaining = data;
+    while let Some(unit) = next_length_prefixed_unit(&mut remaining, length_size) {
+        output.put_slice(&ANNEX_B_START_CODE);
+        output.put_slice(unit?);
+    }
+
+    Ok(())
+}
+
+fn check_length_size(length_size: usize) -> Result<(), CodecError> {
+    if (1..=4).contains(&length_size) {
+        Ok(())
+    } else {
+        Err(CodecError::MalformedBitstream(
+            "NAL unit length size must be between 1 and 4 bytes",
+        ))
+    }
+}
+
+/// Position of the first `00 00 01` sequence.
+fn find_start_code(data: &[u8]) -> Option<usize> {
+    data.windows(3).position(|window| window == [0, 0, 1])
+}
+
+/// Splits the unit at the start of `remaining`, which follows a start code, and moves past the next start code.
+fn next_annex_b_unit<'a>(remaining: &mut &'a [u8]) -> Option<&'a [u8]> {
+    loop {
+        if remaining.is_empty() {
+            return None;
+        }
+
+        let (unit, rest) = match find_start_code(remaining) {
+            Some(position) => (&remaining[..position], &remaining[position + 3..]),
+            None => (*remaining, &[][..]),
+        };
+        *remaining = rest;
+
+        // Trailing zeros are either the first byte of a 4 bytes start code or padding between units
+        let unit_end = unit
+            .iter()
+            .rposition(|&byte| byte != 0)
+            .map_or(0, |position| position + 1);
+        if unit_end > 0 {
+            return Some(&unit[..unit_end]);
+        }
+    }
+}
+
+fn next_length_prefixed_unit<'a>(remaining: &mut &'a [u8], length_size: usize) -> Option<Result<&'a [u8], CodecError>> {
+    if remaining.is_empty() {
+        return None;
+    }
+
+    if remaining.len() < length_size {
+        *remaining = &[];
+        return Some(Err(CodecError::MalformedBitstream("truncated NAL unit length")));
+    }
+
+    let (length_bytes, rest) = remaining.split_at(length_size);
+    let length = length_bytes
+        .iter()
+        .fold(0usize, |length, &byte| (length << 8) | byte as usize);
+
+    if rest.len() < length {
+        *remaining = &[];
+        return Some(Err(CodecError::MalformedBitstream("truncated NAL unit")));
+    }
+
+    let (unit, rest) = rest.split_at(length);
+    *remaining = rest;
+    Some(Ok(unit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // AUD, SPS, PPS and IDR slice, with 4 and 3 bytes start codes and trailing zeros after the PPS
+    const ANNEX_B_ACCESS_UNIT: &[u8] = &[
+        0, 0, 0, 1, 0x09, 0xf0, //
+        0, 0, 0, 1, 0x67, 0x42, 0xc0, 0x1e, //
+        0, 0, 1, 0x68, 0xce, 0x3c, 0x80, 0, 0, //
+        0, 0, 1, 0x65, 0x88, 0x84, 0x00, 0x21,
+    ];
+
+    const LENGTH_PREFIXED_ACCESS_UNIT: &[u8] = &[
+        0, 0, 0, 2, 0x09, 0xf0, //
+        0, 0, 0, 4, 0x67, 0x42, 0xc0, 0x1e, //
+        0, 0, 0, 4, 0x68, 0xce, 0x3c, 0x80, //
+        0, 0, 0, 5, 0x65, 0x88, 0x84, 0x00, 0x21,
+    ];
+
+    #[test]
+    fn splits_annex_b_nal_units() {
+        let nal_types: Vec<NalType> = AnnexBNalUnits::new(NalCodec::H264, ANNEX_B_ACCESS_UNIT)
+            .map(|nal_unit| nal_unit.nal_type())
+            .collect();
+
+        assert_eq!(
+            nal_types,
+            [
+                NalType::AccessUnitDelimiter,
+                NalType::Sps,
+                NalType::Pps,
+                NalType::RandomAccess
+            ]
+        );
+    }
+
+    #[test]
+    fn converts_annex_b_to_length_prefixed() {
+        let mut length_prefixed = Vec::new();
+        annex_b_to_length_prefixed(ANNEX_B_ACCESS_UNIT, 4, &mut length_prefixed).unwrap();
+        assert_eq!(length_prefixed, LENGTH_PREFIXED_ACCESS_UNIT);
+
+        let mut annex_b = Vec::new();
+        length_prefixed_to_annex_b(&length_prefixed, 4, &mut annex_b).unwrap();
+
+        let mut round_trip = Vec::new();
+        annex_b_to_length_prefixed(&annex_b, 4, &mut round_trip).unwrap();
+        assert_eq!(round_trip, LENGTH_PREFIXED_ACCESS_UNIT);
+    }
+
+    #[test]
+    fn converts_with_short_length_prefixes() {
+        let mut length_prefixed = Vec::new();
+        annex_b_to_length_prefixed(ANNEX_B_ACCESS_UNIT, 2, &mut length_prefixed).unwrap();
+
+        let nal_units: Vec<&[u8]> = LengthPrefixedNalUnits::new(NalCodec::H264, &length_prefixed, 2)
+            .unwrap()
+            .map(|nal_unit| nal_unit.unwrap().data())
+            .collect();
+        let expected: Vec<&[u8]> = AnnexBNalUnits::new(NalCodec::H264, ANNEX_B_ACCESS_UNIT)
+            .map(|nal_unit| nal_unit.data())
+            .collect();
+        assert_eq!(nal_units, expected);
+
+        let large_nal_unit = [&[0, 0, 1, 0x65][..], &[0x88; 256]].concat();
+        assert!(annex_b_to_length_prefixed(&large_nal_unit, 1, &mut Vec::new()).is_err());
+        assert!(annex_b_to_length_prefixed(ANNEX_B_ACCESS_UNIT, 5, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_length_prefixed_units() {
+        let truncated = &LENGTH_PREFIXED_ACCESS_UNIT[..LENGTH_PREFIXED_ACCESS_UNIT.len() - 1];
+        let nal_units: Vec<_> = LengthPrefixedNalUnits::new(NalCodec::H264, truncated, 4)
+            .unwrap()
+            .collect();
+
+        assert_eq!(nal_units.len(), 4);
+        assert!(nal_units[..3].iter().all(Result::is_ok));
+        assert!(nal_units[3].is_err());
+
+        assert!(length_prefixed_to_annex_b(&[0, 0], 4, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn removes_emulation_prevention() {
+        let escaped = [0x11, 0, 0, 3, 0, 0, 0, 3, 1, 0, 0, 3];
+        assert_eq!(&*remove_emulation_prevention(&escaped), [0x11, 0, 0, 0, 0, 0, 1, 0, 0]);
+
+        // A 0x03 byte which does not follow two zero bytes is kept, without copying the data
+        let unescaped = [0, 3, 0, 0, 1];
+        assert!(matches!(remove_emulation_prevention(&unescaped), Cow::Borrowed(data) if data == unescaped));
+    }
+
+    #[test]
+    fn classifies_hevc_nal_units() {
+        let nal_type = |header: [u8; 2]| NalUnit::new(NalCodec::Hevc, &header).nal_type();
+
+        assert_eq!(nal_type([0x40, 0x01]), NalType::Vps);
+        assert_eq!(nal_type([0x42, 0x01]), NalType::Sps);
+        assert_eq!(nal_type([0x44, 0x01]), NalType::Pps);
+        assert_eq!(nal_type([0x26, 0x01]), NalType::RandomAccess);
+        assert_eq!(nal_type([0x2a, 0x01]), NalType::RandomAccess);
+        assert_eq!(nal_type([0x02, 0x01]), NalType::Slice);
+        assert_eq!(nal_type([0x4e, 0x01]), NalType::Sei);
+    }
+}
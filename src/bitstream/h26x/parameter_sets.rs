@@ -0,0 +1,452 @@
+use crate::{bitstream::bits::BitReader, error::CodecError};
+
+use super::{NalCodec, NalType, NalUnit};
+
+const PICTURE_SIZE_OUT_OF_RANGE: CodecError = CodecError::MalformedBitstream("picture size out of range");
+
+/// Fields of an H.264 sequence parameter set, with the picture size already cropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct H264Sps {
+    pub profile_idc: u8,
+    pub constraint_flags: u8,
+    pub level_idc: u8,
+    pub sps_id: u32,
+    pub chroma_format_idc: u32,
+    pub bit_depth_luma: u32,
+    pub bit_depth_chroma: u32,
+    pub frame_mbs_only: bool,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl H264Sps {
+    pub fn parse(nal_unit: &NalUnit) -> Result<Self, CodecError> {
+        check_nal_unit(nal_unit, NalCodec::H264, NalType::Sps)?;
+
+        let rbsp = nal_unit.rbsp();
+        let mut reader = BitReader::new(&rbsp);
+
+        let profile_idc = reader.read_bits(8)? as u8;
+        let constraint_flags = reader.read_bits(8)? as u8;
+        let level_idc = reader.read_bits(8)? as u8;
+        let sps_id = reader.read_ue()?;
+
+        let mut chroma_format_idc = 1;
+        let mut separate_colour_plane = false;
+        let mut bit_depth_luma = 8;
+        let mut bit_depth_chroma = 8;
+
+        // Only the high profiles signal the chroma format and bit depths
+        if matches!(
+            profile_idc,
+            100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+        ) {
+            chroma_format_idc = reader.read_ue()?;
+            if chroma_format_idc == 3 {
+                separate_colour_plane = reader.read_bit()?;
+            }
+            bit_depth_luma = read_bit_depth(&mut reader)?;
+            bit_depth_chroma = read_bit_depth(&mut reader)?;
+            reader.skip_bits(1)?; // qpprime_y_zero_transform_bypass_flag
+
+            let seq_scaling_matrix_present = reader.read_bit()?;
+            if seq_scaling_matrix_present {
+                let lists_count = if chroma_format_idc == 3 { 12 } else { 8 };
+                for list in 0..lists_count {
+                    if reader.read_bit()? {
+                        skip_scaling_list(&mut reader, if list < 6 { 16 } else { 64 })?;
+                    }
+                }
+            }
+        }
+
+        reader.read_ue()?; // log2_max_frame_num_minus4
+
+        let pic_order_cnt_type = reader.read_ue()?;
+        match pic_order_cnt_type {
+            0 => {
+                reader.read_ue()?; // log2_max_pic_order_cnt_lsb_minus4
+            }
+            1 => {
+                reader.skip_bits(1)?; // delta_pic_order_always_zero_flag
+                reader.read_se()?; // offset_for_non_ref_pic
+                reader.read_se()?; // offset_for_top_to_bottom_field
+                let cycle_length = reader.read_ue()?;
+                for _ in 0..cycle_length {
+                    reader.read_se()?; // offset_for_ref_frame
+                }
+            }
+            _ => {}
+        }
+
+        reader.read_ue()?; // max_num_ref_frames
+        reader.skip_bits(1)?; // gaps_in_frame_num_value_allowed_flag
+
+        let width_in_mbs = reader.read_ue()? + 1;
+        let height_in_map_units = reader.read_ue()? + 1;
+        let frame_mbs_only = reader.read_bit()?;
+        if !frame_mbs_only {
+            reader.skip_bits(1)?; // mb_adaptive_frame_field_flag
+        }
+        reader.skip_bits(1)?; // direct_8x8_inference_flag
+
+        let (mut crop_left, mut crop_right, mut crop_top, mut crop_bottom) = (0, 0, 0, 0);
+        if reader.read_bit()? {
+            crop_left = reader.read_ue()?;
+            crop_right = reader.read_ue()?;
+            crop_top = reader.read_ue()?;
+            crop_bottom = reader.read_ue()?;
+        }
+
+        let field_factor = if frame_mbs_only { 1 } else { 2 };
+        let chroma_array_type = if separate_colour_plane { 0 } else { chroma_format_idc };
+        let (crop_unit_x, crop_unit_y) = match chroma_array_type {
+            0 => (1, field_factor),
+            _ => {
+                let (sub_width, sub_height) = chroma_subsampling(chroma_format_idc);
+                (sub_width, sub_height * field_factor)
+            }
+        };
+
+        let full_width = width_in_mbs
+            .checked_mul(16)
+            .ok_or(PICTURE_SIZE_OUT_OF_RANGE)?;
+        let full_height = height_in_map_units
+            .checked_mul(16 * field_factor)
+            .ok_or(PICTURE_SIZE_OUT_OF_RANGE)?;
+
+        Ok(Self {
+            profile_idc,
+            constraint_flags,
+            level_idc,
+            sps_id,
+            chroma_format_idc,
+            bit_depth_luma,
+            bit_depth_chroma,
+            frame_mbs_only,
+            width: cropped(full_width, crop_unit_x, crop_left, crop_right)?,
+            height: cropped(full_height, crop_unit_y, crop_top, crop_bottom)?,
+        })
+    }
+}
+
+/// General profile, tier and level of an HEVC stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HevcProfileTierLevel {
+    pub profile_space: u8,
+    pub tier_flag: bool,
+    pub profile_idc: u8,
+    pub level_idc: u8,
+}
+
+/// Fields of an HEVC video parameter set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HevcVps {
+    pub vps_id: u8,
+    pub max_layers: u8,
+    pub max_sub_layers: u8,
+    pub profile_tier_level: HevcProfileTierLevel,
+}
+
+impl HevcVps {
+    pub fn parse(nal_unit: &NalUnit) -> Result<Self, CodecError> {
+        check_nal_unit(nal_unit, NalCodec::Hevc, NalType::Vps)?;
+
+        let rbsp = nal_unit.rbsp();
+        let mut reader = BitReader::new(&rbsp);
+
+        let vps_id = reader.read_bits(4)? as u8;
+        reader.skip_bits(2)?; // vps_base_layer_internal_flag, vps_base_layer_available_flag
+        let max_layers = reader.read_bits(6)? as u8 + 1;
+        let max_sub_layers = reader.read_bits(3)? as u8 + 1;
+        reader.skip_bits(1 + 16)?; // vps_temporal_id_nesting_flag, vps_reserved_0xffff_16bits
+
+        let profile_tier_level = parse_profile_tier_level(&mut reader, max_sub_layers)?;
+
+        Ok(Self {
+            vps_id,
+            max_layers,
+            max_sub_layers,
+            profile_tier_level,
+        })
+    }
+}
+
+/// Fields of an HEVC sequence parameter set, with the picture size already cropped to the conformance window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HevcSps {
+    pub vps_id: u8,
+    pub sps_id: u32,
+    pub max_sub_layers: u8,
+    pub profile_tier_level: HevcProfileTierLevel,
+    pub chroma_format_idc: u32,
+    pub bit_depth_luma: u32,
+    pub bit_depth_chroma: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl HevcSps {
+    pub fn parse(nal_unit: &NalUnit) -> Result<Self, CodecError> {
+        check_nal_unit(nal_unit, NalCodec::Hevc, NalType::Sps)?;
+
+        let rbsp = nal_unit.rbsp();
+        let mut reader = BitReader::new(&rbsp);
+
+        let vps_id = reader.read_bits(4)? as u8;
+        let max_sub_layers = reader.read_bits(3)? as u8 + 1;
+        reader.skip_bits(1)?; // sps_temporal_id_nesting_flag
+
+        let profile_tier_level = parse_profile_tier_level(&mut reader, max_sub_layers)?;
+
+        let sps_id = reader.read_ue()?;
+        let chroma_format_idc = reader.read_ue()?;
+        let separate_colour_plane = chroma_format_idc == 3 && reader.read_bit()?;
+
+        let full_width = reader.read_ue()?;
+        let full_height = reader.read_ue()?;
+
+        let (mut crop_left, mut crop_right, mut crop_top, mut crop_bottom) = (0, 0, 0, 0);
+        if reader.read_bit()? {
+            crop_left = reader.read_ue()?;
+            crop_right = reader.read_ue()?;
+            crop_top = reader.read_ue()?;
+            crop_bottom = reader.read_ue()?;
+        }
+
+        let bit_depth_luma = read_bit_depth(&mut reader)?;
+        let bit_depth_chroma = read_bit_depth(&mut reader)?;
+
+        // Colour planes coded separately are cropped as monochrome pictures
+        let chroma_array_type = if separate_colour_plane { 0 } else { chroma_format_idc };
+        let (sub_width, sub_height) = chroma_subsampling(chroma_array_type);
+
+        Ok(Self {
+            vps_id,
+            sps_id,
+            max_sub_layers,
+            profile_tier_level,
+            chroma_format_idc,
+            bit_depth_luma,
+            bit_depth_chroma,
+            width: cropped(full_width, sub_width, crop_left, crop_right)?,
+            height: cropped(full_height, sub_height, crop_top, crop_bottom)?,
+        })
+    }
+}
+
+fn check_nal_unit(nal_unit: &NalUnit, codec: NalCodec, nal_type: NalType) -> Result<(), CodecError> {
+    if nal_unit.codec() != codec || nal_unit.nal_type() != nal_type {
+        return Err(CodecError::MalformedBitstream("unexpected NAL unit type"));
+    }
+
+    Ok(())
+}
+
+/// Reads a `bit_depth_minus8` field, which never exceeds 8 in the profiles of either codec.
+fn read_bit_depth(reader: &mut BitReader) -> Result<u32, CodecError> {
+    let bit_depth_minus8 = reader.read_ue()?;
+    if bit_depth_minus8 > 8 {
+        return Err(CodecError::MalformedBitstream("bit depth out of range"));
+    }
+
+    Ok(bit_depth_minus8 + 8)
+}
+
+fn skip_scaling_list(reader: &mut BitReader, size: usize) -> Result<(), CodecError> {
+    let mut last_scale = 8;
+    let mut next_scale = 8;
+    for _ in 0..size {
+        if next_scale != 0 {
+            let delta_scale = reader.read_se()?;
+            if !(-128..=127).contains(&delta_scale) {
+                return Err(CodecError::MalformedBitstream("scaling list delta out of range"));
+            }
+            next_scale = (last_scale + delta_scale + 256) % 256;
+        }
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_profile_tier_level(reader: &mut BitReader, max_sub_layers: u8) -> Result<HevcProfileTierLevel, CodecError> {
+    let profile_space = reader.read_bits(2)? as u8;
+    let tier_flag = reader.read_bit()?;
+    let profile_idc = reader.read_bits(5)? as u8;
+
+    // Compatibility flags, source flags and reserved bits
+    reader.skip_bits(32 + 4 + 43 + 1)?;
+    let level_idc = reader.read_bits(8)? as u8;
+
+    let sub_layers = max_sub_layers as usize - 1;
+    let mut sub_layer_flags = Vec::with_capacity(sub_layers);
+    for _ in 0..sub_layers {
+        let profile_present = reader.read_bit()?;
+        let level_present = reader.read_bit()?;
+        sub_layer_flags.push((profile_present, level_present));
+    }
+    if sub_layers > 0 {
+        reader.skip_bits(2 * (8 - sub_layers))?; // reserved_zero_2bits
+    }
+
+    for (profile_present, level_present) in sub_layer_flags {
+        if profile_present {
+            reader.skip_bits(88)?;
+        }
+        if level_present {
+            reader.skip_bits(8)?;
+        }
+    }
+
+    Ok(HevcProfileTierLevel {
+        profile_space,
+        tier_flag,
+        profile_idc,
+        level_idc,
+    })
+}
+
+/// Horizontal and vertical chroma subsampling factors of a `chroma_format_idc`.
+fn chroma_subsampling(chroma_format_idc: u32) -> (u32, u32) {
+    match chroma_format_idc {
+        1 => (2, 2),
+        2 => (2, 1),
+        _ => (1, 1),
+    }
+}
+
+fn cropped(size: u32, crop_unit: u32, crop_start: u32, crop_end: u32) -> Result<u32, CodecError> {
+    crop_start
+        .checked_add(crop_end)
+        .and_then(|crop| crop.checked_mul(crop_unit))
+        .and_then(|crop| size.checked_sub(crop))
+        .ok_or(CodecError::MalformedBitstream("cropping larger than the picture"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // High profile, level 4.0, 1920x1088 macroblocks cropped to 1080 rows
+    const H264_HIGH_SPS: &[u8] = &[0x67, 0x64, 0x00, 0x28, 0xac, 0xd9, 0x40, 0x78, 0x02, 0x27, 0xe5, 0x40];
+
+    // Constrained baseline profile, level 3.0, 640x480
+    const H264_BASELINE_SPS: &[u8] = &[0x67, 0x42, 0xc0, 0x1e, 0xe4, 0x40, 0x50, 0x1e, 0xc8];
+
+    // Main profile, level 3.1, as written by x265
+    const HEVC_VPS: &[u8] = &[
+        0x40, 0x01, 0x0c, 0x01, 0xff, 0xff, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00,
+        0x03, 0x00, 0x5d, 0x95, 0x98, 0x09,
+    ];
+
+    // Main 10 profile, level 4.1, 1920x1088 cropped to 1080 rows by the conformance window
+    const HEVC_MAIN10_SPS: &[u8] = &[
+        0x42, 0x01, 0x01, 0x02, 0x20, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x00, 0x7b,
+        0xa0, 0x03, 0xc0, 0x80, 0x11, 0x07, 0xca, 0xdc,
+    ];
+
+    #[test]
+    fn parses_h264_sps() {
+        let sps = H264Sps::parse(&NalUnit::new(NalCodec::H264, H264_HIGH_SPS)).unwrap();
+        assert_eq!(
+            sps,
+            H264Sps {
+                profile_idc: 100,
+                constraint_flags: 0,
+                level_idc: 40,
+                sps_id: 0,
+                chroma_format_idc: 1,
+                bit_depth_luma: 8,
+                bit_depth_chroma: 8,
+                frame_mbs_only: true,
+                width: 1920,
+                height: 1080,
+            }
+        );
+
+        let sps = H264Sps::parse(&NalUnit::new(NalCodec::H264, H264_BASELINE_SPS)).unwrap();
+        assert_eq!((sps.profile_idc, sps.constraint_flags, sps.level_idc), (66, 0xc0, 30));
+        assert_eq!((sps.width, sps.height), (640, 480));
+    }
+
+    #[test]
+    fn parses_hevc_vps() {
+        let vps = HevcVps::parse(&NalUnit::new(NalCodec::Hevc, HEVC_VPS)).unwrap();
+        assert_eq!(
+            vps,
+            HevcVps {
+                vps_id: 0,
+                max_layers: 1,
+                max_sub_layers: 1,
+                profile_tier_level: HevcProfileTierLevel {
+                    profile_space: 0,
+                    tier_flag: false,
+                    profile_idc: 1,
+                    level_idc: 93,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn parses_hevc_sps() {
+        let sps = HevcSps::parse(&NalUnit::new(NalCodec::Hevc, HEVC_MAIN10_SPS)).unwrap();
+        assert_eq!(
+            sps,
+            HevcSps {
+                vps_id: 0,
+                sps_id: 0,
+                max_sub_layers: 1,
+                profile_tier_level: HevcProfileTierLevel {
+                    profile_space: 0,
+                    tier_flag: false,
+                    profile_idc: 2,
+                    level_idc: 123,
+                },
+                chroma_format_idc: 1,
+                bit_depth_luma: 10,
+                bit_depth_chroma: 10,
+                width: 1920,
+                height: 1080,
+            }
+        );
+    }
+
+    #[test]
+    fn skips_scaling_lists() {
+        // Same SPS as `H264_HIGH_SPS`, with a flat scaling list
+        let sps = [
+            0x67, 0x64, 0x00, 0x28, 0xad, 0x84, 0x40, 0x6c, 0xa0, 0x3c, 0x01, 0x13, 0xf2, 0xa0,
+        ];
+        let sps = H264Sps::parse(&NalUnit::new(NalCodec::H264, &sps)).unwrap();
+        assert_eq!((sps.width, sps.height), (1920, 1080));
+
+        let out_of_range_delta = [
+            0x67, 0x64, 0x00, 0x28, 0xad, 0x80, 0x00, 0x00, 0x03, 0x00, 0xff, 0xff, 0xff, 0xfe, 0x80,
+        ];
+        assert!(H264Sps::parse(&NalUnit::new(NalCodec::H264, &out_of_range_delta)).is_err());
+    }
+
+    #[test]
+    fn crops_hevc_separate_colour_planes() {
+        // Range extensions profile, 4:4:4 with separate colour planes, 1920x1088 cropped to 1080 rows
+        let sps = [
+            0x42, 0x01, 0x01, 0x04, 0x08, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x00, 0x7b,
+            0x92, 0x00, 0x78, 0x10, 0x02, 0x20, 0xf8, 0x9e,
+        ];
+        let sps = HevcSps::parse(&NalUnit::new(NalCodec::Hevc, &sps)).unwrap();
+
+        assert_eq!((sps.profile_tier_level.profile_idc, sps.chroma_format_idc), (4, 3));
+        assert_eq!((sps.bit_depth_luma, sps.width, sps.height), (8, 1920, 1080));
+    }
+
+    #[test]
+    fn rejects_other_nal_units() {
+        assert!(H264Sps::parse(&NalUnit::new(NalCodec::H264, &[0x68, 0xce, 0x3c, 0x80])).is_err());
+        assert!(HevcSps::parse(&NalUnit::new(NalCodec::Hevc, HEVC_VPS)).is_err());
+        assert!(H264Sps::parse(&NalUnit::new(NalCodec::H264, &H264_HIGH_SPS[..6])).is_err());
+    }
+}
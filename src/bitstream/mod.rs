@@ -0,0 +1,46 @@
+//! Helpers to inspect and reframe encoded bitstreams without going through libavcodec.
+
+use crate::ffi;
+
+use self::{
+    av1::{ObuType, Obus},
+    h26x::{AnnexBNalUnits, NalCodec, NalType},
+    vpx::{superframe_frames, Vp8FrameHeader, Vp9FrameHeader},
+};
+
+pub mod av1;
+mod bits;
+pub mod h26x;
+pub mod vpx;
+
+/// Size of a coded or rendered picture, as signalled in a sequence or frame header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Tells whether an access unit can be decoded on its own, or `None` for codecs whose bitstream is not inspected.
+///
+/// H.264 and HEVC access units are expected in Annex B. AV1 temporal units are classified by the sequence header
+/// that encoders repeat at the start of each coded video sequence, since parsing their frame headers needs the
+/// sequence header anyway.
+pub fn is_keyframe(codec_id: ffi::AVCodecID, access_unit: &[u8]) -> Option<bool> {
+    let keyframe = match codec_id {
+        ffi::AVCodecID_AV_CODEC_ID_H264 => AnnexBNalUnits::new(NalCodec::H264, access_unit)
+            .any(|nal_unit| nal_unit.nal_type() == NalType::RandomAccess),
+        ffi::AVCodecID_AV_CODEC_ID_HEVC => AnnexBNalUnits::new(NalCodec::Hevc, access_unit)
+            .any(|nal_unit| nal_unit.nal_type() == NalType::RandomAccess),
+        ffi::AVCodecID_AV_CODEC_ID_VP8 => Vp8FrameHeader::parse(access_unit).is_ok_and(|header| header.keyframe),
+        ffi::AVCodecID_AV_CODEC_ID_VP9 => superframe_frames(access_unit)
+            .ok()
+            .and_then(|frames| frames.first().copied())
+            .is_some_and(|frame| Vp9FrameHeader::parse(frame).is_ok_and(|header| header.keyframe)),
+        ffi::AVCodecID_AV_CODEC_ID_AV1 => Obus::new(access_unit)
+            .map_while(Result::ok)
+            .any(|obu| obu.obu_type() == ObuType::SequenceHeader),
+        _ => return None,
+    };
+
+    Some(keyframe)
+}
@@ -0,0 +1,349 @@
+//! Frame header parsing for VP8 and VP9 streams.
+
+use crate::error::CodecError;
+
+use super::{bits::BitReader, FrameSize};
+
+const VP8_START_CODE: [u8; 3] = [0x9d, 0x01, 0x2a];
+const VP9_SYNC_CODE: u32 = 0x49_83_42;
+const VP9_COLOR_SPACE_RGB: u32 = 7;
+
+/// Fields of a VP8 frame tag and, for keyframes, of the frame header that follows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vp8FrameHeader {
+    pub keyframe: bool,
+    pub version: u8,
+    pub show_frame: bool,
+    pub first_partition_size: u32,
+    /// Only signalled by keyframes, the other frames keep the size of the last one
+    pub size: Option<FrameSize>,
+    /// Upscaling factors of the keyframe, as the 2 bits code stored next to each dimension
+    pub scaling: Option<(u8, u8)>,
+}
+
+impl Vp8FrameHeader {
+    pub fn parse(data: &[u8]) -> Result<Self, CodecError> {
+        if data.len() < 3 {
+            return Err(CodecError::MalformedBitstream("truncated VP8 frame tag"));
+        }
+
+        let tag = u32::from_le_bytes([data[0], data[1], data[2], 0]);
+        let keyframe = tag & 1 == 0;
+        let version = ((tag >> 1) & 0x07) as u8;
+        let show_frame = (tag >> 4) & 1 == 1;
+        let first_partition_size = tag >> 5;
+
+        let mut size = None;
+        let mut scaling = None;
+        if keyframe {
+            if data.len() < 10 {
+                return Err(CodecError::MalformedBitstream("truncated VP8 keyframe header"));
+            }
+            if data[3..6] != VP8_START_CODE {
+                return Err(CodecError::MalformedBitstream("invalid VP8 start code"));
+            }
+
+            let horizontal = u16::from_le_bytes([data[6], data[7]]);
+            let vertical = u16::from_le_bytes([data[8], data[9]]);
+            size = Some(FrameSize {
+                width: (horizontal & 0x3fff) as u32,
+                height: (vertical & 0x3fff) as u32,
+            });
+            scaling = Some(((horizontal >> 14) as u8, (vertical >> 14) as u8));
+        }
+
+        Ok(Self {
+            keyframe,
+            version,
+            show_frame,
+            first_partition_size,
+            size,
+            scaling,
+        })
+    }
+}
+
+/// Fields of a VP9 uncompressed frame header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vp9FrameHeader {
+    pub profile: u8,
+    pub show_existing_frame: bool,
+    pub keyframe: bool,
+    pub intra_only: bool,
+    pub show_frame: bool,
+    pub error_resilient_mode: bool,
+    /// Only signalled by keyframes and intra-only frames
+    pub bit_depth: Option<u32>,
+    /// Only signalled by keyframes and intra-only frames, the others take it from their references
+    pub size: Option<FrameSize>,
+    pub render_size: Option<FrameSize>,
+}
+
+impl Vp9FrameHeader {
+    /// Parses the header of a single frame, superframes must be split with [`superframe_frames`] first.
+    pub fn parse(data: &[u8]) -> Result<Self, CodecError> {
+        let mut reader = BitReader::new(data);
+
+        if reader.read_bits(2)? != 2 {
+            return Err(CodecError::MalformedBitstream("invalid VP9 frame marker"));
+        }
+
+        let profile_low_bit = reader.read_bits(1)?;
+        let profile_high_bit = reader.read_bits(1)?;
+        let profile = ((profile_high_bit << 1) | profile_low_bit) as u8;
+        if profile == 3 {
+            reader.skip_bits(1)?; // reserved_zero
+        }
+
+        let mut header = Self {
+            profile,
+            show_existing_frame: reader.read_bit()?,
+            keyframe: false,
+            intra_only: false,
+            show_frame: true,
+            error_resilient_mode: false,
+            bit_depth: None,
+            size: None,
+            render_size: None,
+        };
+
+        if header.show_existing_frame {
+            return Ok(header);
+        }
+
+        header.keyframe = !reader.read_bit()?;
+        header.show_frame = reader.read_bit()?;
+        header.error_resilient_mode = reader.read_bit()?;
+
+        if header.keyframe {
+            read_sync_code(&mut reader)?;
+            header.bit_depth = Some(read_color_config(&mut reader, profile)?);
+        } else {
+            header.intra_only = !header.show_frame && reader.read_bit()?;
+            if !header.error_resilient_mode {
+                reader.skip_bits(2)?; // reset_frame_context
+            }
+
+            if !header.intra_only {
+                return Ok(header);
+            }
+
+            read_sync_code(&mut reader)?;
+            header.bit_depth = Some(match profile {
+                0 => 8,
+                _ => read_color_config(&mut reader, profile)?,
+            });
+            reader.skip_bits(8)?; // refresh_frame_flags
+        }
+
+        let size = read_size(&mut reader)?;
+        let render_and_frame_size_different = reader.read_bit()?;
+        header.size = Some(size);
+        header.render_size = Some(if render_and_frame_size_different {
+            read_size(&mut reader)?
+        } else {
+            size
+        });
+
+        Ok(header)
+    }
+}
+
+/// Splits a VP9 superframe into the frames listed by its trailing index.
+///
+/// A buffer without superframe index holds a single frame and is returned as is.
+pub fn superframe_frames(data: &[u8]) -> Result<Vec<&[u8]>, CodecError> {
+    let Some(&marker) = data.last() else {
+        return Ok(Vec::new());
+    };
+
+    if marker & 0xe0 != 0xc0 {
+        return Ok(vec![data]);
+    }
+
+    let frames_count = (marker & 0x07) as usize + 1;
+    let size_bytes = ((marker >> 3) & 0x03) as usize + 1;
+    let index_size = 2 + size_bytes * frames_count;
+
+    // The index starts with a copy of the marker, otherwise the last byte just happens to look like one
+    if data.len() < index_size || data[data.len() - index_size] != marker {
+        return Ok(vec![data]);
+    }
+
+    let (mut remaining, index) = data.split_at(data.len() - index_size);
+    let mut frames = Vec::with_capacity(frames_count);
+    for size_field in index[1..index.len() - 1].chunks_exact(size_bytes) {
+        let frame_size = size_field
+            .iter()
+            .rev()
+            .fold(0usize, |size, &byte| (size << 8) | byte as usize);
+
+        if remaining.len() < frame_size {
+            return Err(CodecError::MalformedBitstream("truncated VP9 superframe"));
+        }
+
+        let (frame, rest) = remaining.split_at(frame_size);
+        frames.push(frame);
+        remaining = rest;
+    }
+
+    Ok(frames)
+}
+
+fn read_sync_code(reader: &mut BitReader) -> Result<(), CodecError> {
+    if reader.read_bits(24)? != VP9_SYNC_CODE {
+        return Err(CodecError::MalformedBitstream("invalid VP9 sync code"));
+    }
+
+    Ok(())
+}
+
+/// Reads a `color_config`, returning the bit depth.
+fn read_color_config(reader: &mut BitReader, profile: u8) -> Result<u32, CodecError> {
+    let bit_depth = match profile {
+        2 | 3 if reader.read_bit()? => 12,
+        2 | 3 => 10,
+        _ => 8,
+    };
+
+    let color_space = reader.read_bits(3)?;
+    let odd_profile = profile == 1 || profile == 3;
+    if color_space != VP9_COLOR_SPACE_RGB {
+        reader.skip_bits(1)?; // color_range
+        if odd_profile {
+            reader.skip_bits(3)?; // subsampling_x, subsampling_y, reserved_zero
+        }
+    } else if odd_profile {
+        reader.skip_bits(1)?; // reserved_zero
+    }
+
+    Ok(bit_depth)
+}
+
+fn read_size(reader: &mut BitReader) -> Result<FrameSize, CodecError> {
+    Ok(FrameSize {
+        width: reader.read_bits(16)? + 1,
+        height: reader.read_bits(16)? + 1,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Shown keyframe of 640x480, with a first partition of 530 bytes
+    const VP8_KEYFRAME: &[u8] = &[0x50, 0x42, 0x00, 0x9d, 0x01, 0x2a, 0x80, 0x02, 0xe0, 0x01];
+
+    // Profile 0 keyframe of 640x480
+    const VP9_KEYFRAME: &[u8] = &[0x82, 0x49, 0x83, 0x42, 0x20, 0x27, 0xf0, 0x1d, 0xf0];
+
+    // Profile 2 keyframe of 1280x720 in 10 bits, rendered at 1920x1080
+    const VP9_PROFILE_2_KEYFRAME: &[u8] = &[
+        0x92, 0x49, 0x83, 0x42, 0x20, 0x27, 0xf8, 0x16, 0x7c, 0x1d, 0xfc, 0x10, 0xdc,
+    ];
+
+    // Profile 0 shown inter frame
+    const VP9_INTER_FRAME: &[u8] = &[0x86, 0x00];
+
+    #[test]
+    fn parses_vp8_frame_headers() {
+        let header = Vp8FrameHeader::parse(VP8_KEYFRAME).unwrap();
+        assert_eq!(
+            header,
+            Vp8FrameHeader {
+                keyframe: true,
+                version: 0,
+                show_frame: true,
+                first_partition_size: 530,
+                size: Some(FrameSize {
+                    width: 640,
+                    height: 480,
+                }),
+                scaling: Some((0, 0)),
+            }
+        );
+
+        let header = Vp8FrameHeader::parse(&[0x51, 0x42, 0x00]).unwrap();
+        assert!(!header.keyframe);
+        assert_eq!(header.size, None);
+
+        let mut invalid_start_code = VP8_KEYFRAME.to_vec();
+        invalid_start_code[3] = 0;
+        assert!(Vp8FrameHeader::parse(&invalid_start_code).is_err());
+        assert!(Vp8FrameHeader::parse(&VP8_KEYFRAME[..6]).is_err());
+    }
+
+    #[test]
+    fn parses_vp9_frame_headers() {
+        let header = Vp9FrameHeader::parse(VP9_KEYFRAME).unwrap();
+        let frame_size = FrameSize {
+            width: 640,
+            height: 480,
+        };
+        assert_eq!(
+            header,
+            Vp9FrameHeader {
+                profile: 0,
+                show_existing_frame: false,
+                keyframe: true,
+                intra_only: false,
+                show_frame: true,
+                error_resilient_mode: false,
+                bit_depth: Some(8),
+                size: Some(frame_size),
+                render_size: Some(frame_size),
+            }
+        );
+
+        let header = Vp9FrameHeader::parse(VP9_PROFILE_2_KEYFRAME).unwrap();
+        assert_eq!((header.profile, header.bit_depth), (2, Some(10)));
+        assert_eq!(
+            header.size,
+            Some(FrameSize {
+                width: 1280,
+                height: 720,
+            })
+        );
+        assert_eq!(
+            header.render_size,
+            Some(FrameSize {
+                width: 1920,
+                height: 1080,
+            })
+        );
+
+        let header = Vp9FrameHeader::parse(VP9_INTER_FRAME).unwrap();
+        assert!(!header.keyframe && !header.intra_only);
+        assert_eq!(header.size, None);
+
+        assert!(Vp9FrameHeader::parse(&[0x42, 0x00]).is_err());
+        assert!(Vp9FrameHeader::parse(&VP9_KEYFRAME[..3]).is_err());
+    }
+
+    #[test]
+    fn splits_superframes() {
+        let mut superframe = [VP9_KEYFRAME, VP9_INTER_FRAME].concat();
+        superframe.extend_from_slice(&[0xc1, VP9_KEYFRAME.len() as u8, VP9_INTER_FRAME.len() as u8, 0xc1]);
+
+        assert_eq!(superframe_frames(&superframe).unwrap(), [VP9_KEYFRAME, VP9_INTER_FRAME]);
+
+        // Sizes on 2 bytes
+        let mut superframe = [VP9_KEYFRAME, VP9_INTER_FRAME].concat();
+        superframe.extend_from_slice(&[0xc9, VP9_KEYFRAME.len() as u8, 0, VP9_INTER_FRAME.len() as u8, 0, 0xc9]);
+
+        assert_eq!(superframe_frames(&superframe).unwrap(), [VP9_KEYFRAME, VP9_INTER_FRAME]);
+    }
+
+    #[test]
+    fn keeps_frames_without_superframe_index() {
+        assert_eq!(superframe_frames(VP9_KEYFRAME).unwrap(), [VP9_KEYFRAME]);
+        assert!(superframe_frames(&[]).unwrap().is_empty());
+
+        // Last byte looking like a marker, without the matching first byte of an index
+        let frame = [0x86, 0x00, 0x00, 0xc0];
+        assert_eq!(superframe_frames(&frame).unwrap(), [&frame[..]]);
+
+        let truncated = [0x86, 0xc1, 0x04, 0x04, 0xc1];
+        assert!(superframe_frames(&truncated).is_err());
+    }
+}
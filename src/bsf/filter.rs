@@ -0,0 +1,129 @@
+use std::ptr::NonNull;
+
+use remotia::traits::FrameProcessor;
+use rsmpeg::{avcodec::AVPacket, error::RsmpegError, UnsafeDerefMut};
+
+use async_trait::async_trait;
+
+use crate::{
+    bitstream::is_keyframe,
+    error::{CodecErrorKind, CodecErrorReport},
+    ffi,
+    packets::{packet_data, packet_from_slice},
+    EncodedPacketSink, EncodedPacketSource, PacketMetadata,
+};
+
+/// Owns an initialized filter chain, freeing it on drop.
+pub(super) struct BitstreamFilterContext {
+    pub(super) raw: NonNull<ffi::AVBSFContext>,
+}
+
+// The filter chain is only ever accessed through the processor which owns it
+unsafe impl Send for BitstreamFilterContext {}
+
+impl Drop for BitstreamFilterContext {
+    fn drop(&mut self) {
+        let mut raw_context = self.raw.as_ptr();
+        unsafe { ffi::av_bsf_free(&mut raw_context) };
+    }
+}
+
+/// Runs the packet buffer of each frame through a chain of bitstream filters, replacing it with the filtered
+/// packets.
+///
+/// The whole buffer is filtered as a single packet, with the frame id as its timestamp. The packet is flagged as a
+/// keyframe when `EncodedPacketSource::contains_keyframe` says so or, when the frame does not tell, when its bitstream
+/// starts a new coded video sequence.
+pub struct BitstreamFilter {
+    pub(super) filters: String,
+    pub(super) codec_id: ffi::AVCodecID,
+    pub(super) context: BitstreamFilterContext,
+}
+
+impl BitstreamFilter {
+    fn filter<F>(&mut self, frame_data: &mut F) -> Result<(), CodecErrorReport>
+    where
+        F: EncodedPacketSink + EncodedPacketSource,
+    {
+        let filter_failed =
+            |error: RsmpegError| CodecErrorReport::from_rsmpeg(CodecErrorKind::BitstreamFilter, &error, &self.filters);
+
+        let Some(input_buffer) = frame_data.get_packet_data_buffer() else {
+            return Err(CodecErrorReport::missing_packet_buffer(&self.filters));
+        };
+
+        // An empty packet would signal the end of the stream to the filters
+        if input_buffer.is_empty() {
+            return Ok(());
+        }
+
+        let mut input_packet = packet_from_slice(input_buffer).map_err(filter_failed)?;
+        input_packet.set_pts(frame_data.get_frame_id());
+
+        let keyframe = frame_data
+            .contains_keyframe()
+            .or_else(|| is_keyframe(self.codec_id, input_buffer))
+            .unwrap_or(false);
+        if keyframe {
+            unsafe {
+                input_packet.deref_mut().flags |= ffi::AV_PKT_FLAG_KEY as i32;
+            }
+        }
+
+        let raw_context = self.context.raw.as_ptr();
+        check(unsafe { ffi::av_bsf_send_packet(raw_context, input_packet.as_mut_ptr()) }).map_err(filter_failed)?;
+
+        frame_data.clear_packet_data();
+        frame_data.clear_packet_metadata();
+
+        let mut written_bytes = 0;
+        loop {
+            let mut output_packet = AVPacket::new();
+            let result = unsafe { ffi::av_bsf_receive_packet(raw_context, output_packet.as_mut_ptr()) };
+            if result == ffi::AVERROR(ffi::EAGAIN) {
+                break;
+            }
+            check(result).map_err(filter_failed)?;
+
+            let data = packet_data(&output_packet);
+            frame_data
+                .write_packet_data(data)
+                .map_err(|_| CodecErrorReport::missing_packet_buffer(&self.filters))?;
+            frame_data.write_packet_metadata(PacketMetadata {
+                keyframe: output_packet.flags & ffi::AV_PKT_FLAG_KEY as i32 != 0,
+                pts: output_packet.pts,
+                dts: output_packet.dts,
+                duration: output_packet.duration,
+                offset: written_bytes,
+                size: data.len(),
+            });
+
+            written_bytes += data.len();
+        }
+
+        Ok(())
+    }
+}
+
+fn check(result: i32) -> Result<(), RsmpegError> {
+    if result < 0 {
+        Err(RsmpegError::AVError(result))
+    } else {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<F> FrameProcessor<F> for BitstreamFilter
+where
+    F: EncodedPacketSink + EncodedPacketSource + Send + 'static,
+{
+    async fn process(&mut self, mut frame_data: F) -> Option<F> {
+        if let Err(report) = self.filter(&mut frame_data) {
+            log::warn!("Unable to filter frame {}: {}", frame_data.get_frame_id(), report);
+            frame_data.report_codec_error(report);
+        }
+
+        Some(frame_data)
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    ffi::CString,
+    ptr::{self, NonNull},
+};
+
+use rsmpeg::avcodec::AVCodec;
+
+use crate::{
+    builder::unwrap_mandatory, error::CodecError, extradata::alloc_extradata, ffi, timestamps::DEFAULT_TIME_BASE,
+};
+
+mod filter;
+
+pub use filter::*;
+
+pub struct BitstreamFilterBuilder {
+    filters: Option<String>,
+    codec_id: Option<String>,
+    extradata: Option<Vec<u8>>,
+    time_base: Option<ffi::AVRational>,
+}
+
+impl Default for BitstreamFilterBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BitstreamFilterBuilder {
+    pub fn new() -> Self {
+        Self {
+            filters: None,
+            codec_id: None,
+            extradata: None,
+            time_base: None,
+        }
+    }
+
+    // Out-of-band parameter sets of the input stream, needed by filters such as `h264_mp4toannexb`
+    builder_set!(extradata, Vec<u8>);
+    builder_set!(time_base, ffi::AVRational);
+
+    /// Comma separated filter chain with optional filter options, such as `h264_metadata=level=4.1,dump_extra`.
+    pub fn filters(mut self, filters: &str) -> Self {
+        self.filters = Some(filters.to_string());
+        self
+    }
+
+    /// Codec of the filtered stream, either as a codec name such as `h264` or as an encoder or decoder name.
+    pub fn codec_id(mut self, codec_id: &str) -> Self {
+        self.codec_id = Some(codec_id.to_string());
+        self
+    }
+
+    pub fn build(self) -> Result<BitstreamFilter, CodecError> {
+        let filters = unwrap_mandatory(self.filters, "filters")?;
+        let codec_id = unwrap_mandatory(self.codec_id, "codec_id")?;
+
+        let raw_codec_id = find_codec_id(&codec_id)?;
+
+        let filters_string =
+            CString::new(filters.as_str()).map_err(|_| CodecError::BitstreamFilterNotFound(filters.clone()))?;
+        let mut raw_context = ptr::null_mut();
+        let result = unsafe { ffi::av_bsf_list_parse_str(filters_string.as_ptr(), &mut raw_context) };
+        let context = match NonNull::new(raw_context) {
+            Some(raw) if result >= 0 => BitstreamFilterContext { raw },
+            _ => return Err(CodecError::BitstreamFilterNotFound(filters)),
+        };
+
+        unsafe {
+            let raw_context = context.raw.as_ptr();
+            let input_parameters = (*raw_context).par_in;
+            (*input_parameters).codec_type = ffi::AVMediaType_AVMEDIA_TYPE_VIDEO;
+            (*input_parameters).codec_id = raw_codec_id;
+            (*raw_context).time_base_in = self.time_base.unwrap_or(DEFAULT_TIME_BASE);
+
+            if let Some(extradata) = &self.extradata {
+                let buffer = alloc_extradata(extradata)
+                    .ok_or_else(|| CodecError::bitstream_filter_failed(ffi::AVERROR(ffi::ENOMEM)))?;
+                (*input_parameters).extradata = buffer;
+                (*input_parameters).extradata_size = extradata.len() as i32;
+            }
+
+            let result = ffi::av_bsf_init(raw_context);
+            if result < 0 {
+                return Err(CodecError::bitstream_filter_failed(result));
+            }
+        }
+
+        Ok(BitstreamFilter {
+            filters,
+            codec_id: raw_codec_id,
+            context,
+        })
+    }
+}
+
+fn find_codec_id(codec_id: &str) -> Result<ffi::AVCodecID, CodecError> {
+    let codec_id_string = CString::new(codec_id).map_err(|_| CodecError::CodecNotFound(codec_id.to_string()))?;
+
+    let descriptor = unsafe { ffi::avcodec_descriptor_get_by_name(codec_id_string.as_ptr()).as_ref() };
+    if let Some(descriptor) = descriptor {
+        return Ok(descriptor.id);
+    }
+
+    AVCodec::find_encoder_by_name(&codec_id_string)
+        .or_else(|| AVCodec::find_decoder_by_name(&codec_id_string))
+        .map(|codec| codec.id)
+        .ok_or_else(|| CodecError::CodecNotFound(codec_id.to_string()))
+}
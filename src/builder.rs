@@ -1,3 +1,5 @@
+use crate::error::CodecError;
+
 macro_rules! builder_set {
     ($attr_name: ident, $attr_type: ty) => {
         pub fn $attr_name(mut self, $attr_name: $attr_type) -> Self {
@@ -7,6 +9,6 @@ macro_rules! builder_set {
     }
 }
 
-pub fn unwrap_mandatory<V>(value: Option<V>) -> V {
-    value.expect("Missing mandatory field")
-}
\ No newline at end of file
+pub fn unwrap_mandatory<V>(value: Option<V>, field: &'static str) -> Result<V, CodecError> {
+    value.ok_or(CodecError::MissingField(field))
+}
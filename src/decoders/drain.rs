@@ -0,0 +1,196 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use rsmpeg::error::RsmpegError;
+
+pub(super) const DEFAULT_DRAIN_QUEUE_CAPACITY: usize = 8;
+
+/// How many decoded frames the `DecoderPuller` takes from the decoder at every step.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DrainMode {
+    /// Receive at most one frame, leaving the others in the decoder for the following steps
+    #[default]
+    Single,
+
+    /// Receive all the ready frames and emit the newest one, dropping the older ones
+    Latest,
+
+    /// Receive all the ready frames and emit them one per step, oldest first. Once the queue holds as many frames as
+    /// its capacity, the oldest ones are dropped
+    Queue,
+}
+
+/// Cloneable handle counting the decoded frames dropped in `DrainMode::Latest`, or because the `DrainMode::Queue` queue
+/// was full.
+#[derive(Clone, Default)]
+pub struct DroppedFramesCounter {
+    dropped_frames: Arc<AtomicU64>,
+}
+
+impl DroppedFramesCounter {
+    pub fn get(&self) -> u64 {
+        self.dropped_frames.load(Ordering::Relaxed)
+    }
+
+    fn add(&self, dropped_frames: u64) {
+        self.dropped_frames
+            .fetch_add(dropped_frames, Ordering::Relaxed);
+    }
+}
+
+/// Receives all the ready frames through `receive_frame`, returning the newest one.
+pub(super) fn receive_latest<T>(
+    mut receive_frame: impl FnMut() -> Result<T, RsmpegError>,
+    dropped_frames_counter: &DroppedFramesCounter,
+) -> Result<T, RsmpegError> {
+    let mut latest_avframe = receive_frame()?;
+
+    let mut dropped_frames = 0;
+    while let Ok(avframe) = receive_frame() {
+        latest_avframe = avframe;
+        dropped_frames += 1;
+    }
+
+    if dropped_frames > 0 {
+        log::debug!("Dropped {} stale decoded frames", dropped_frames);
+        dropped_frames_counter.add(dropped_frames);
+    }
+
+    Ok(latest_avframe)
+}
+
+/// Moves all the ready frames received through `receive_frame` to the queue, dropping the oldest ones beyond its capacity, and returns the oldest
+/// queued one. Receive errors other than the decoder waiting for packets or being flushed are returned instead.
+pub(super) fn receive_queued<T>(
+    mut receive_frame: impl FnMut() -> Result<T, RsmpegError>,
+    queued_avframes: &mut VecDeque<T>,
+    queue_capacity: usize,
+    dropped_frames_counter: &DroppedFramesCounter,
+) -> Result<T, RsmpegError> {
+    let mut dropped_frames = 0;
+    let receive_error = loop {
+        match receive_frame() {
+            Ok(avframe) => {
+                if queued_avframes.len() >= queue_capacity.max(1) {
+                    queued_avframes.pop_front();
+                    dropped_frames += 1;
+                }
+                queued_avframes.push_back(avframe);
+            }
+            Err(error) => break error,
+        }
+    };
+
+    if dropped_frames > 0 {
+        log::debug!(
+            "Decoded frames queue full, dropped the {} oldest frames",
+            dropped_frames
+        );
+        dropped_frames_counter.add(dropped_frames);
+    }
+
+    log::trace!("{} decoded frames queued", queued_avframes.len());
+
+    match receive_error {
+        RsmpegError::DecoderDrainError | RsmpegError::DecoderFlushedError => {
+            queued_avframes.pop_front().ok_or(receive_error)
+        }
+        // Reported at this step, the queued frames are emitted at the following ones
+        error => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out the given frames, then tells that the decoder needs more packets.
+    fn decoder(frames: &[u32]) -> impl FnMut() -> Result<u32, RsmpegError> {
+        let mut frames: VecDeque<u32> = frames.iter().copied().collect();
+        move || frames.pop_front().ok_or(RsmpegError::DecoderDrainError)
+    }
+
+    #[test]
+    fn receives_the_latest_frame() {
+        let counter = DroppedFramesCounter::default();
+
+        assert_eq!(receive_latest(decoder(&[1, 2, 3]), &counter).ok(), Some(3));
+        assert_eq!(counter.get(), 2);
+    }
+
+    #[test]
+    fn reports_an_empty_decoder_in_latest_mode() {
+        let counter = DroppedFramesCounter::default();
+
+        assert!(matches!(
+            receive_latest(decoder(&[]), &counter),
+            Err(RsmpegError::DecoderDrainError)
+        ));
+        assert_eq!(counter.get(), 0);
+    }
+
+    #[test]
+    fn emits_queued_frames_oldest_first() {
+        let counter = DroppedFramesCounter::default();
+        let mut queue = VecDeque::new();
+
+        assert_eq!(receive_queued(decoder(&[1, 2]), &mut queue, 4, &counter).ok(), Some(1));
+        assert_eq!(receive_queued(decoder(&[3]), &mut queue, 4, &counter).ok(), Some(2));
+        assert_eq!(receive_queued(decoder(&[]), &mut queue, 4, &counter).ok(), Some(3));
+        assert!(matches!(
+            receive_queued(decoder(&[]), &mut queue, 4, &counter),
+            Err(RsmpegError::DecoderDrainError)
+        ));
+        assert_eq!(counter.get(), 0);
+    }
+
+    #[test]
+    fn drops_the_oldest_frames_of_a_full_queue() {
+        let counter = DroppedFramesCounter::default();
+        let mut queue = VecDeque::new();
+
+        assert_eq!(
+            receive_queued(decoder(&[1, 2, 3, 4, 5]), &mut queue, 3, &counter).ok(),
+            Some(3)
+        );
+        assert_eq!(queue, [4, 5]);
+        assert_eq!(counter.get(), 2);
+    }
+
+    #[test]
+    fn emits_queued_frames_once_flushed() {
+        let counter = DroppedFramesCounter::default();
+        let mut queue = VecDeque::from([1]);
+        let flushed = || Err::<u32, _>(RsmpegError::DecoderFlushedError);
+
+        assert_eq!(receive_queued(flushed, &mut queue, 4, &counter).ok(), Some(1));
+        assert!(matches!(
+            receive_queued(flushed, &mut queue, 4, &counter),
+            Err(RsmpegError::DecoderFlushedError)
+        ));
+    }
+
+    #[test]
+    fn reports_receive_errors_before_queued_frames() {
+        let counter = DroppedFramesCounter::default();
+        let mut queue = VecDeque::from([1]);
+        let mut results = VecDeque::from([Ok(2), Err(RsmpegError::AVError(-22))]);
+        let failing = || {
+            results
+                .pop_front()
+                .unwrap_or(Err(RsmpegError::DecoderDrainError))
+        };
+
+        assert!(matches!(
+            receive_queued(failing, &mut queue, 4, &counter),
+            Err(RsmpegError::AVError(-22))
+        ));
+        assert_eq!(queue, [1, 2]);
+        assert_eq!(receive_queued(decoder(&[]), &mut queue, 4, &counter).ok(), Some(1));
+    }
+}
@@ -0,0 +1,27 @@
+use rsmpeg::avutil::AVFrame;
+
+use crate::error::CodecError;
+
+use super::AVFrameExtractor;
+
+/// Extracts frames through a closure, for one-off layouts which do not deserve a dedicated extractor.
+///
+/// Plane rows can be read safely through `planes::Planes`.
+pub struct FnExtractor<C> {
+    pub(super) extract_fn: C,
+}
+
+impl<C> FnExtractor<C> {
+    pub fn new(extract_fn: C) -> Self {
+        Self { extract_fn }
+    }
+}
+
+impl<F, C> AVFrameExtractor<F> for FnExtractor<C>
+where
+    C: FnMut(&AVFrame, &mut F) -> Result<(), CodecError>,
+{
+    fn extract(&mut self, avframe: &AVFrame, frame_data: &mut F) -> Result<(), CodecError> {
+        (self.extract_fn)(avframe, frame_data)
+    }
+}
@@ -0,0 +1,13 @@
+use rsmpeg::avutil::AVFrame;
+
+use crate::error::CodecError;
+
+pub mod function;
+pub mod nv12;
+pub mod packed;
+pub mod rgba;
+pub mod yuv420p;
+
+pub trait AVFrameExtractor<F> {
+    fn extract(&mut self, avframe: &AVFrame, frame_data: &mut F) -> Result<(), CodecError>;
+}
@@ -0,0 +1,29 @@
+use remotia::{buffers::BytesMut, traits::BorrowMutFrameProperties};
+use rsmpeg::avutil::AVFrame;
+
+use crate::error::CodecError;
+
+use super::{packed::PackedFrameExtractor, AVFrameExtractor};
+
+/// Extracts NV12 frames as the Y plane followed by the interleaved UV plane.
+pub struct NV12FrameExtractor<K> {
+    pub(super) packed: PackedFrameExtractor<K>,
+}
+
+impl<K> NV12FrameExtractor<K> {
+    pub fn new(nv12_buffer_key: K) -> Self {
+        Self {
+            packed: PackedFrameExtractor::nv12(nv12_buffer_key),
+        }
+    }
+}
+
+impl<F, K> AVFrameExtractor<F> for NV12FrameExtractor<K>
+where
+    K: Send + Copy,
+    F: BorrowMutFrameProperties<K, BytesMut> + Send + 'static,
+{
+    fn extract(&mut self, avframe: &AVFrame, frame_data: &mut F) -> Result<(), CodecError> {
+        self.packed.extract(avframe, frame_data)
+    }
+}
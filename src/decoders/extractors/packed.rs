@@ -0,0 +1,84 @@
+use remotia::{buffers::BytesMut, traits::BorrowMutFrameProperties};
+use rsmpeg::avutil::AVFrame;
+
+use crate::{
+    error::CodecError,
+    ffi,
+    planes::{check_pixel_format, Planes},
+};
+
+use super::AVFrameExtractor;
+
+/// Writes frames to a buffer, packing each plane after the previous one with no line padding.
+///
+/// Frames of any pixel format are accepted, unless the extractor is restricted to a few of them, as the constructors
+/// named after a layout do.
+pub struct PackedFrameExtractor<K> {
+    pub(super) buffer_key: K,
+    pub(super) pixel_formats: &'static [ffi::AVPixelFormat],
+}
+
+impl<K> PackedFrameExtractor<K> {
+    pub fn new(buffer_key: K) -> Self {
+        Self::with_pixel_formats(buffer_key, &[])
+    }
+
+    /// Only accepts frames in one of `pixel_formats`, or in any format when empty.
+    pub fn with_pixel_formats(buffer_key: K, pixel_formats: &'static [ffi::AVPixelFormat]) -> Self {
+        Self {
+            buffer_key,
+            pixel_formats,
+        }
+    }
+
+    /// Frames made of 4-bytes packed pixels, such as RGBA and BGRA ones.
+    pub fn rgba(buffer_key: K) -> Self {
+        Self::with_pixel_formats(
+            buffer_key,
+            &[
+                ffi::AVPixelFormat_AV_PIX_FMT_RGBA,
+                ffi::AVPixelFormat_AV_PIX_FMT_BGRA,
+                ffi::AVPixelFormat_AV_PIX_FMT_ARGB,
+                ffi::AVPixelFormat_AV_PIX_FMT_ABGR,
+                ffi::AVPixelFormat_AV_PIX_FMT_RGB0,
+                ffi::AVPixelFormat_AV_PIX_FMT_BGR0,
+            ],
+        )
+    }
+
+    /// NV12 frames, as the Y plane followed by the interleaved UV plane.
+    pub fn nv12(buffer_key: K) -> Self {
+        Self::with_pixel_formats(buffer_key, &[ffi::AVPixelFormat_AV_PIX_FMT_NV12])
+    }
+
+    /// YUV420P frames, as the Y, U and V planes one after the other.
+    pub fn yuv420p(buffer_key: K) -> Self {
+        Self::with_pixel_formats(
+            buffer_key,
+            &[
+                ffi::AVPixelFormat_AV_PIX_FMT_YUV420P,
+                ffi::AVPixelFormat_AV_PIX_FMT_YUVJ420P,
+            ],
+        )
+    }
+}
+
+impl<F, K> AVFrameExtractor<F> for PackedFrameExtractor<K>
+where
+    K: Send + Copy,
+    F: BorrowMutFrameProperties<K, BytesMut> + Send + 'static,
+{
+    fn extract(&mut self, avframe: &AVFrame, frame_data: &mut F) -> Result<(), CodecError> {
+        if !self.pixel_formats.is_empty() {
+            check_pixel_format(avframe.format, self.pixel_formats)?;
+        }
+
+        let destination_buffer = frame_data
+            .get_mut_ref(&self.buffer_key)
+            .ok_or(CodecError::MissingBuffer)?;
+
+        Planes::new(avframe)?.copy_to_packed(destination_buffer);
+
+        Ok(())
+    }
+}
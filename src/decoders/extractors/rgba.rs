@@ -0,0 +1,31 @@
+use remotia::{buffers::BytesMut, traits::BorrowMutFrameProperties};
+use rsmpeg::avutil::AVFrame;
+
+use crate::error::CodecError;
+
+use super::{packed::PackedFrameExtractor, AVFrameExtractor};
+
+/// Extracts frames made of 4-bytes packed pixels, such as RGBA and BGRA ones.
+pub struct RGBAFrameExtractor<K> {
+    pub(super) packed: PackedFrameExtractor<K>,
+}
+
+pub type BGRAFrameExtractor<K> = RGBAFrameExtractor<K>;
+
+impl<K> RGBAFrameExtractor<K> {
+    pub fn new(rgba_buffer_key: K) -> Self {
+        Self {
+            packed: PackedFrameExtractor::rgba(rgba_buffer_key),
+        }
+    }
+}
+
+impl<F, K> AVFrameExtractor<F> for RGBAFrameExtractor<K>
+where
+    K: Send + Copy,
+    F: BorrowMutFrameProperties<K, BytesMut> + Send + 'static,
+{
+    fn extract(&mut self, avframe: &AVFrame, frame_data: &mut F) -> Result<(), CodecError> {
+        self.packed.extract(avframe, frame_data)
+    }
+}
@@ -0,0 +1,29 @@
+use remotia::{buffers::BytesMut, traits::BorrowMutFrameProperties};
+use rsmpeg::avutil::AVFrame;
+
+use crate::error::CodecError;
+
+use super::{packed::PackedFrameExtractor, AVFrameExtractor};
+
+/// Extracts YUV420P frames as the Y, U and V planes one after the other.
+pub struct YUV420PFrameExtractor<K> {
+    pub(super) packed: PackedFrameExtractor<K>,
+}
+
+impl<K> YUV420PFrameExtractor<K> {
+    pub fn new(yuv420p_buffer_key: K) -> Self {
+        Self {
+            packed: PackedFrameExtractor::yuv420p(yuv420p_buffer_key),
+        }
+    }
+}
+
+impl<F, K> AVFrameExtractor<F> for YUV420PFrameExtractor<K>
+where
+    K: Send + Copy,
+    F: BorrowMutFrameProperties<K, BytesMut> + Send + 'static,
+{
+    fn extract(&mut self, avframe: &AVFrame, frame_data: &mut F) -> Result<(), CodecError> {
+        self.packed.extract(avframe, frame_data)
+    }
+}
@@ -1,74 +1,211 @@
-use std::{ffi::CString, sync::Arc};
+use std::{collections::VecDeque, ffi::CString, sync::Arc};
 
-use rsmpeg::avcodec::{AVCodec, AVCodecContext, AVCodecParserContext};
+use rsmpeg::{
+    avcodec::{AVCodec, AVCodecContext, AVCodecParserContext},
+    error::RsmpegError,
+    UnsafeDerefMut,
+};
 
 use tokio::sync::Mutex;
 
-use crate::{builder::unwrap_mandatory, options::Options, scaling::Scaler};
-
+use crate::{
+    builder::unwrap_mandatory,
+    error::CodecError,
+    extradata::{alloc_extradata, is_length_prefixed_config},
+    ffi,
+    options::Options,
+    scaling::ScalerBuilder,
+    timestamps::{TimestampMode, Timestamper, DEFAULT_TIME_BASE},
+};
+
+pub mod extractors;
 mod utils;
 
+mod drain;
 mod puller;
 mod pusher;
 
+pub use drain::*;
 pub use puller::*;
 pub use pusher::*;
 
-pub struct DecoderBuilder {
+/// How encoded buffers are turned into the packets sent to the decoder.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecoderInputMode {
+    /// Parse buffers when the codec has a parser, send them as whole packets otherwise
+    #[default]
+    Auto,
+
+    /// Split buffers into packets with the codec parser, failing to build when there is none
+    Parsed,
+
+    /// Send each buffer as a single packet, as returned by the encoder
+    Packets,
+}
+
+pub struct DecoderBuilder<X> {
     codec_id: Option<String>,
+    extractor: Option<X>,
     options: Option<Options>,
-    scaler: Option<Scaler>,
+    scaler: Option<ScalerBuilder>,
+    output_pixel_format: Option<ffi::AVPixelFormat>,
+    time_base: Option<ffi::AVRational>,
+    timestamp_mode: Option<TimestampMode>,
+    input_mode: Option<DecoderInputMode>,
+    complete_frames: Option<bool>,
+    drain_mode: Option<DrainMode>,
+    drain_queue_capacity: Option<usize>,
+    extradata: Option<Vec<u8>>,
 }
 
-impl Default for DecoderBuilder {
+impl<X> Default for DecoderBuilder<X> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl DecoderBuilder {
+impl<X> DecoderBuilder<X> {
     pub fn new() -> Self {
         Self {
             codec_id: None,
+            extractor: None,
             options: None,
             scaler: None,
+            output_pixel_format: None,
+            time_base: None,
+            timestamp_mode: None,
+            input_mode: None,
+            complete_frames: None,
+            drain_mode: None,
+            drain_queue_capacity: None,
+            extradata: None,
         }
     }
 
+    builder_set!(extractor, X);
     builder_set!(options, Options);
-    builder_set!(scaler, Scaler);
+    builder_set!(scaler, ScalerBuilder);
+    builder_set!(output_pixel_format, ffi::AVPixelFormat);
+    builder_set!(time_base, ffi::AVRational);
+    builder_set!(timestamp_mode, TimestampMode);
+    builder_set!(input_mode, DecoderInputMode);
+
+    // When each pushed buffer holds whole frames, the parser can output them right away instead of waiting for the
+    // start of the next frame, saving one frame of latency
+    builder_set!(complete_frames, bool);
+    builder_set!(drain_mode, DrainMode);
+
+    // Most frames held by `DrainMode::Queue`, beyond which the oldest ones are dropped
+    builder_set!(drain_queue_capacity, usize);
+
+    // Out-of-band parameter sets, either in Annex B or as an avcC/hvcC record. A record means that packets are
+    // length-prefixed, hence the automatic input mode sends them whole since they cannot be parsed
+    builder_set!(extradata, Vec<u8>);
 
     pub fn codec_id(mut self, codec_id: &str) -> Self {
         self.codec_id = Some(codec_id.to_string());
         self
     }
 
-    pub fn build(self) -> (DecoderPusher, DecoderPuller) {
-        let codec_id = unwrap_mandatory(self.codec_id);
-        let options = self.options.unwrap_or_default().to_av_dict();
+    pub fn build(self) -> Result<(DecoderPusher, DecoderPuller<X>), CodecError> {
+        let codec_id = unwrap_mandatory(self.codec_id, "codec_id")?;
+        let extractor = unwrap_mandatory(self.extractor, "extractor")?;
+        let options = self.options.unwrap_or_default().to_av_dict()?;
+
+        // The scaler input is only known once the first frame has been decoded
+        let scaler_builder = match (self.scaler, self.output_pixel_format) {
+            (Some(scaler_builder), Some(output_pixel_format)) => {
+                Some(scaler_builder.output_pixel_format(output_pixel_format))
+            }
+            (Some(scaler_builder), None) => Some(scaler_builder),
+            (None, Some(output_pixel_format)) => Some(ScalerBuilder::new().output_pixel_format(output_pixel_format)),
+            (None, None) => None,
+        };
+
+        let time_base = self.time_base.unwrap_or(DEFAULT_TIME_BASE);
+        let timestamper = Timestamper::new(self.timestamp_mode.unwrap_or_default(), time_base);
+
+        let codec_id_string =
+            CString::new(codec_id.as_str()).map_err(|_| CodecError::CodecNotFound(codec_id.clone()))?;
+        let decoder = AVCodec::find_decoder_by_name(&codec_id_string)
+            .ok_or_else(|| CodecError::CodecNotFound(codec_id.clone()))?;
+
+        let length_prefixed = self
+            .extradata
+            .as_deref()
+            .is_some_and(is_length_prefixed_config);
+        let mut parser_context = match self.input_mode.unwrap_or_default() {
+            DecoderInputMode::Auto if length_prefixed => {
+                log::info!("Length-prefixed packets for '{}', sending whole buffers", codec_id);
+                None
+            }
+            DecoderInputMode::Auto => {
+                let parser_context = AVCodecParserContext::find(decoder.id);
+                if parser_context.is_none() {
+                    log::info!("No parser for '{}', sending whole buffers as packets", codec_id);
+                }
+                parser_context
+            }
+            DecoderInputMode::Parsed => Some(
+                AVCodecParserContext::find(decoder.id).ok_or_else(|| CodecError::ParserNotFound(codec_id.clone()))?,
+            ),
+            DecoderInputMode::Packets => None,
+        };
 
-        let codec_id_string = CString::new(codec_id).unwrap();
-        let decoder = AVCodec::find_decoder_by_name(&codec_id_string).unwrap();
-        let parser_context = AVCodecParserContext::find(decoder.id).unwrap();
+        if let Some(parser_context) = &mut parser_context {
+            if self.complete_frames.unwrap_or(false) {
+                unsafe {
+                    parser_context.deref_mut().flags |= ffi::PARSER_FLAG_COMPLETE_FRAMES as i32;
+                }
+            }
+        }
 
         let decode_context = {
             let mut decode_context = AVCodecContext::new(&decoder);
-            decode_context.open(Some(options)).unwrap();
+            decode_context.set_time_base(time_base);
+            unsafe {
+                decode_context.deref_mut().pkt_timebase = time_base;
+            }
+
+            if let Some(extradata) = &self.extradata {
+                let buffer = alloc_extradata(extradata)
+                    .ok_or_else(|| CodecError::open_failed(RsmpegError::AVError(ffi::AVERROR(ffi::ENOMEM))))?;
+                unsafe {
+                    decode_context.deref_mut().extradata = buffer;
+                    decode_context.deref_mut().extradata_size = extradata.len() as i32;
+                }
+            }
+
+            decode_context
+                .open(Some(options))
+                .map_err(CodecError::open_failed)?;
 
             Arc::new(Mutex::new(decode_context))
         };
 
-        let scaler = unwrap_mandatory(self.scaler);
-
-        (
+        Ok((
             DecoderPusher {
+                codec_name: codec_id.clone(),
+                // Keyframes cannot be told from length-prefixed packets, which are then never waited for
+                keyframe_codec_id: (!length_prefixed).then_some(decoder.id),
+                awaiting_keyframe: false,
                 decode_context: decode_context.clone(),
                 parser_context,
+                timestamper: timestamper.clone(),
             },
             DecoderPuller {
+                codec_name: codec_id,
                 decode_context: decode_context.clone(),
-                scaler,
+                scaler_builder,
+                scaler: None,
+                output_geometry: None,
+                timestamper,
+                extractor,
+                drain_mode: self.drain_mode.unwrap_or_default(),
+                queued_avframes: VecDeque::new(),
+                queue_capacity: self.drain_queue_capacity.unwrap_or(drain::DEFAULT_DRAIN_QUEUE_CAPACITY),
+                dropped_frames_counter: DroppedFramesCounter::default(),
             },
-        )
+        ))
     }
 }
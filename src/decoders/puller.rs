@@ -1,7 +1,7 @@
-use std::sync::Arc;
+use std::{collections::VecDeque, sync::Arc};
 
 use log::debug;
-use rsmpeg::{avcodec::AVCodecContext, error::RsmpegError};
+use rsmpeg::{avcodec::AVCodecContext, avutil::AVFrame, error::RsmpegError};
 
 use remotia::{
     traits::{FrameProcessor},
@@ -10,47 +10,152 @@ use remotia::{
 use async_trait::async_trait;
 use tokio::sync::Mutex;
 
-use crate::{scaling::Scaler, FFMpegCodec};
+use crate::{
+    error::{CodecErrorKind, CodecErrorReport},
+    ffi,
+    scaling::{FrameGeometry, Scaler, ScalerBuilder},
+    timestamps::Timestamper,
+    DecodedFrameSink,
+};
+
+use super::{
+    drain::{receive_latest, receive_queued},
+    extractors::AVFrameExtractor,
+    DrainMode, DroppedFramesCounter,
+};
 
-pub struct DecoderPuller {
+pub struct DecoderPuller<X> {
+    pub(super) codec_name: String,
     pub(super) decode_context: Arc<Mutex<AVCodecContext>>,
-    pub(super) scaler: Scaler,
+    pub(super) scaler_builder: Option<ScalerBuilder>,
+    pub(super) scaler: Option<Scaler>,
+    pub(super) output_geometry: Option<FrameGeometry>,
+    pub(super) timestamper: Timestamper,
+    pub(super) extractor: X,
+    pub(super) drain_mode: DrainMode,
+    pub(super) queued_avframes: VecDeque<AVFrame>,
+    pub(super) queue_capacity: usize,
+    pub(super) dropped_frames_counter: DroppedFramesCounter,
+}
+
+impl<X> DecoderPuller<X> {
+    pub fn dropped_frames_counter(&self) -> DroppedFramesCounter {
+        self.dropped_frames_counter.clone()
+    }
 }
 
 #[async_trait]
-impl<F> FrameProcessor<F> for DecoderPuller
+impl<F, X> FrameProcessor<F> for DecoderPuller<X>
 where
-    F: FFMpegCodec + Send + 'static,
+    X: AVFrameExtractor<F> + Send,
+    F: DecodedFrameSink + Send + 'static,
 {
     async fn process(&mut self, mut frame_data: F) -> Option<F> {
         let mut decode_context = self.decode_context.lock().await;
-        match decode_context.receive_frame() {
+        let receive_result = match self.drain_mode {
+            DrainMode::Single => decode_context.receive_frame(),
+            DrainMode::Latest => receive_latest(|| decode_context.receive_frame(), &self.dropped_frames_counter),
+            DrainMode::Queue => receive_queued(
+                || decode_context.receive_frame(),
+                &mut self.queued_avframes,
+                self.queue_capacity,
+                &self.dropped_frames_counter,
+            ),
+        };
+
+        match receive_result {
             Ok(codec_avframe) => {
                 log::trace!("Received AVFrame: {:#?}", codec_avframe);
-                frame_data.set_frame_id(codec_avframe.pts);
+                frame_data.set_frame_id(self.timestamper.frame_id(codec_avframe.pts));
 
-                self.scaler.scale_input(&codec_avframe);
+                let previous_output_geometry = self.output_geometry;
 
-                let output_avframe = &mut self.scaler.scaled_frame_mut();
+                let scale_result = scale(&self.scaler_builder, &mut self.scaler, &self.codec_name, &codec_avframe);
+                let output_avframe = match scale_result {
+                    Ok(output_avframe) => output_avframe,
+                    Err(report) => {
+                        frame_data.report_codec_error(report);
+                        return Some(frame_data);
+                    }
+                };
 
-                let linesize = output_avframe.linesize;
-                let height = output_avframe.height as usize;
+                let output_geometry = FrameGeometry::of(output_avframe);
+                if previous_output_geometry != Some(output_geometry) {
+                    frame_data.report_geometry_change(output_geometry);
+                }
 
-                let linesize = linesize[0] as usize;
-                let data = unsafe { std::slice::from_raw_parts(output_avframe.data[0], height * linesize) };
+                if let Err(error) = self.extractor.extract(output_avframe, &mut frame_data) {
+                    log::warn!("Unable to extract the decoded frame: {}", error);
+                    frame_data.report_codec_error(CodecErrorReport::from_codec_error(
+                        CodecErrorKind::Extract,
+                        &error,
+                        &self.codec_name,
+                    ));
+                }
 
-                frame_data.write_decoded_buffer(data);
+                self.output_geometry = Some(output_geometry);
             }
             Err(RsmpegError::DecoderDrainError) => {
                 debug!("No frames to be pulled");
                 frame_data.report_decoder_drain_error();
             }
             Err(RsmpegError::DecoderFlushedError) => {
-                panic!("Decoder has been flushed unexpectedly");
+                log::warn!("Decoder has been flushed unexpectedly");
+                frame_data.report_codec_error(CodecErrorReport::new(
+                    CodecErrorKind::DecoderFlushed,
+                    Some(ffi::AVERROR_EOF),
+                    &self.codec_name,
+                ));
+            }
+            Err(error) => {
+                log::warn!("Unhandled codec error during frame receive: {}", error);
+                frame_data.report_codec_error(CodecErrorReport::from_rsmpeg(
+                    CodecErrorKind::ReceiveFrame,
+                    &error,
+                    &self.codec_name,
+                ));
             }
-            Err(e) => panic!("{:?}", e),
         }
 
         Some(frame_data)
     }
 }
+
+/// Scales the decoded frame, (re)building the scaler whenever the decoded geometry changes.
+fn scale<'a>(
+    scaler_builder: &Option<ScalerBuilder>,
+    scaler: &'a mut Option<Scaler>,
+    codec_name: &str,
+    codec_avframe: &'a AVFrame,
+) -> Result<&'a AVFrame, CodecErrorReport> {
+    let scaler_builder = match scaler_builder {
+        Some(scaler_builder) => scaler_builder,
+        None => return Ok(codec_avframe),
+    };
+
+    let decoded_geometry = FrameGeometry::of(codec_avframe);
+    let rebuilt_scaler = match scaler.take() {
+        Some(mut scaler) if scaler.input_geometry() != decoded_geometry => {
+            log::info!("Decoded geometry changed to {:?}", decoded_geometry);
+            scaler.rebuild(decoded_geometry).map(|_| scaler)
+        }
+        Some(scaler) => Ok(scaler),
+        None => scaler_builder
+            .clone()
+            .input_geometry(decoded_geometry)
+            .build(),
+    };
+
+    let rebuilt_scaler = rebuilt_scaler.map_err(|error| {
+        log::warn!("Unable to build the scaler: {}", error);
+        CodecErrorReport::from_codec_error(CodecErrorKind::Reconfiguration, &error, codec_name)
+    })?;
+    let scaler = scaler.insert(rebuilt_scaler);
+
+    scaler.scale_input(codec_avframe).map_err(|error| {
+        log::warn!("Unable to scale the decoded frame: {}", error);
+        CodecErrorReport::from_rsmpeg(CodecErrorKind::Scaling, &error, codec_name)
+    })?;
+
+    Ok(scaler.scaled_frame())
+}
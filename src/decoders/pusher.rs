@@ -10,38 +10,77 @@ use remotia::{
 use async_trait::async_trait;
 use tokio::sync::Mutex;
 
-use crate::FFMpegCodec;
+use crate::{
+    bitstream::is_keyframe,
+    error::{CodecErrorKind, CodecErrorReport},
+    ffi,
+    timestamps::Timestamper,
+    EncodedPacketSource,
+};
 
-use super::utils::parse_and_send_packets;
+use super::utils::{parse_and_send_packets, send_whole_packet};
 
 pub struct DecoderPusher {
-    pub(super) parser_context: AVCodecParserContext,
+    pub(super) codec_name: String,
+    pub(super) keyframe_codec_id: Option<ffi::AVCodecID>,
+    pub(super) awaiting_keyframe: bool,
+    pub(super) parser_context: Option<AVCodecParserContext>,
     pub(super) decode_context: Arc<Mutex<AVCodecContext>>,
+    pub(super) timestamper: Timestamper,
 }
 
 #[async_trait]
 impl<F> FrameProcessor<F> for DecoderPusher
 where
-    F: FFMpegCodec + Send + 'static,
+    F: EncodedPacketSource + Send + 'static,
 {
     async fn process(&mut self, mut frame_data: F) -> Option<F> {
-        let frame_id = frame_data.get_frame_id();
+        let packet_pts = self.timestamper.packet_pts(frame_data.get_frame_id());
 
-        let encoded_packets_buffer = frame_data.get_packet_data_buffer();
+        let Some(encoded_packets_buffer) = frame_data.get_packet_data_buffer() else {
+            debug!("Dropping frame, reason: missing packet buffer");
+            frame_data.report_codec_error(CodecErrorReport::missing_packet_buffer(&self.codec_name));
+            return Some(frame_data);
+        };
         // let encoded_packets_buffer = &encoded_buffer[..encoded_buffer.len()];
 
+        if frame_data.reported_frame_loss() > 0 {
+            self.awaiting_keyframe = true;
+        }
+
+        if self.awaiting_keyframe {
+            let keyframe = frame_data
+                .contains_keyframe()
+                .or_else(|| is_keyframe(self.keyframe_codec_id?, encoded_packets_buffer))
+                .unwrap_or(true);
+            if !keyframe {
+                debug!("Dropping frame, reason: waiting for a keyframe after frame loss");
+                frame_data.report_codec_error(CodecErrorReport::new(
+                    CodecErrorKind::PacketLoss,
+                    None,
+                    &self.codec_name,
+                ));
+                return Some(frame_data);
+            }
+            self.awaiting_keyframe = false;
+        }
+
         let mut decode_context = self.decode_context.lock().await;
 
-        let send_result = parse_and_send_packets(
-            &mut decode_context,
-            &mut self.parser_context,
-            encoded_packets_buffer,
-            frame_id,
-        );
+        let send_result = match &mut self.parser_context {
+            Some(parser_context) => {
+                parse_and_send_packets(&mut decode_context, parser_context, encoded_packets_buffer, packet_pts)
+            }
+            None => send_whole_packet(&mut decode_context, encoded_packets_buffer, packet_pts),
+        };
 
         if let Err(error) = send_result {
             debug!("Dropping frame, reason: {:?}", error);
-            frame_data.report_codec_error();
+            frame_data.report_codec_error(CodecErrorReport::from_rsmpeg(
+                CodecErrorKind::SendPacket,
+                &error,
+                &self.codec_name,
+            ));
             return Some(frame_data);
         }
 
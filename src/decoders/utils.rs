@@ -1,100 +1,120 @@
 use log::{debug, trace};
 use rsmpeg::{
     avcodec::{AVCodecContext, AVCodecParserContext, AVPacket},
-    UnsafeDerefMut,
+    error::RsmpegError,
+    ffi, UnsafeDerefMut,
 };
 
-pub fn parse_and_send_packets(
+use crate::packets::packet_from_slice;
+
+/// Sends the whole input buffer to the decoder as a single packet, without parsing it.
+pub fn send_whole_packet(
     decode_context: &mut AVCodecContext,
-    parser_context: &mut AVCodecParserContext,
     input_buffer: &[u8],
-    frame_id: i64,
-) -> Result<(), ()> {
-    let mut packet = AVPacket::new();
-    let mut parsed_offset = 0;
+    packet_pts: i64,
+) -> Result<(), RsmpegError> {
+    // An empty packet would put the decoder in draining mode
+    if input_buffer.is_empty() {
+        debug!("Skipping empty input buffer");
+        return Ok(());
+    }
 
-    packet.set_pts(frame_id);
+    let mut packet = packet_from_slice(input_buffer)?;
+    packet.set_pts(packet_pts);
 
-    unsafe {
-        let raw = decode_context.as_ptr();
-        trace!("Raw context: {:#?}", *raw);
-    }
+    debug!(
+        "Sending whole packet (timestamp: {}, size: {})...",
+        packet_pts,
+        input_buffer.len()
+    );
 
+    decode_context.send_packet(Some(&packet))
+}
+
+/// Feeds a chunk of the encoded stream to the parser, sending every packet it completes to the decoder.
+///
+/// Chunks may split or merge access units freely: the parser keeps incomplete packets across calls, and each
+/// completed packet is stamped with the timestamp of the chunk in which it started.
+pub fn parse_and_send_packets(
+    decode_context: &mut AVCodecContext,
+    parser_context: &mut AVCodecParserContext,
+    input_buffer: &[u8],
+    packet_pts: i64,
+) -> Result<(), RsmpegError> {
     debug!(
         "Parsing packets (timestamp: {}, input buffer size: {})...",
-        frame_id,
+        packet_pts,
         input_buffer.len()
     );
 
+    let mut parsed_offset = 0;
     while parsed_offset < input_buffer.len() {
-        let (get_packet, offset) = {
-            let this = &mut *parser_context;
-            let packet: &mut AVPacket = &mut packet;
-            let mut packet_data = packet.data;
-            let mut packet_size = packet.size;
-            
-            let mut offset = 0;
-            loop {
-                let current_offset = unsafe {
-                    rsmpeg::ffi::av_parser_parse2(
-                        this.as_mut_ptr(),
-                        decode_context.as_mut_ptr(),
-                        &mut packet_data,
-                        &mut packet_size,
-                        input_buffer.as_ptr(),
-                        input_buffer.len() as i32,
-                        packet.pts,
-                        packet.dts,
-                        packet.pos,
-                    )
-                };
-
-                log::trace!("Parsing current packet size: {}", packet.size);
-                log::trace!("Parsing current offset: {}", current_offset);
-
-                offset += current_offset;
-
-                if packet_size > 0 {
-                    break;
-                }
-            };
-
-            unsafe {
-                packet.deref_mut().data = packet_data;
-                packet.deref_mut().size = packet_size;
-            }
-
-            log::trace!("Parsing final packet size: {}", packet.size);
-            log::trace!("Parsing final offset: {}", offset);
-
-            (packet.size != 0, offset as usize)
+        let remaining_buffer = &input_buffer[parsed_offset..];
+
+        let mut packet_data = std::ptr::null_mut();
+        let mut packet_size = 0;
+        let consumed = unsafe {
+            ffi::av_parser_parse2(
+                parser_context.as_mut_ptr(),
+                decode_context.as_mut_ptr(),
+                &mut packet_data,
+                &mut packet_size,
+                remaining_buffer.as_ptr(),
+                remaining_buffer.len() as i32,
+                packet_pts,
+                ffi::AV_NOPTS_VALUE,
+                -1,
+            )
         };
 
-        if get_packet {
-            unsafe {
-                let raw = packet.as_ptr();
-                trace!("Decoded raw packet: {:#?}", *raw);
-            }
-
-            let result = decode_context.send_packet(Some(&packet));
-
-            match result {
-                Ok(_) => {
-                    trace!("Sent packet successfully");
-                }
-                Err(e) => {
-                    debug!("Error on send packet: {}", e);
-                    return Err(());
-                }
-            }
-
-            packet = AVPacket::new();
-        } else {
-            debug!("No more packets to be sent");
+        if consumed < 0 {
+            return Err(RsmpegError::AVError(consumed));
         }
 
-        parsed_offset += offset;
+        trace!(
+            "Parser consumed {} bytes, completed packet size: {}",
+            consumed,
+            packet_size
+        );
+        parsed_offset += consumed as usize;
+
+        if packet_size > 0 {
+            send_parsed_packet(
+                decode_context,
+                packet_data,
+                packet_size,
+                parser_context.pts,
+                parser_context.dts,
+            )?;
+        } else if consumed == 0 {
+            // Nothing consumed and nothing completed, the parser needs no more from this chunk
+            break;
+        }
     }
 
     Ok(())
 }
+
+fn send_parsed_packet(
+    decode_context: &mut AVCodecContext,
+    packet_data: *mut u8,
+    packet_size: i32,
+    pts: i64,
+    dts: i64,
+) -> Result<(), RsmpegError> {
+    // The packet data belongs to the parser, the decoder copies it since the packet is not reference counted
+    let mut packet = AVPacket::new();
+    unsafe {
+        packet.deref_mut().data = packet_data;
+        packet.deref_mut().size = packet_size;
+    }
+    packet.set_pts(pts);
+    packet.set_dts(dts);
+
+    trace!("Sending parsed packet (timestamp: {}, size: {})", pts, packet_size);
+
+    decode_context.send_packet(Some(&packet)).map_err(|error| {
+        debug!("Error on send packet: {}", error);
+        error
+    })
+}
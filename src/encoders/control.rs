@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, PoisonError,
+    },
+};
+
+use rsmpeg::{avcodec::AVCodecContext, UnsafeDerefMut};
+
+use tokio::sync::Mutex;
+
+use crate::{
+    error::{av_error_message, CodecError},
+    extradata::extradata_of,
+    ffi,
+    options::{option_c_string, Options},
+};
+
+use super::KeyframeRequester;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderSetting {
+    BitRate,
+    MaxRate,
+    BufferSize,
+    Crf,
+    Qp,
+    KeyframeInterval,
+}
+
+impl EncoderSetting {
+    /// Tells whether the encoder picks up changes of this setting after the codec context has been opened.
+    pub fn is_reconfigurable(&self, codec_name: &str) -> bool {
+        match self {
+            // Keyframes are forced by the pusher, hence the interval works with any codec
+            Self::KeyframeInterval => true,
+            Self::BitRate | Self::MaxRate | Self::BufferSize => {
+                matches!(codec_name, "libx264" | "libx264rgb" | "h264_nvenc" | "hevc_nvenc")
+            }
+            Self::Crf | Self::Qp => matches!(codec_name, "libx264" | "libx264rgb"),
+        }
+    }
+}
+
+/// Rate control options changed at runtime, applied again on top of the builder options whenever the encode context
+/// is reopened after a geometry change.
+#[derive(Clone, Default)]
+pub(crate) struct RateControlOverrides {
+    options: Arc<std::sync::Mutex<HashMap<String, String>>>,
+}
+
+impl RateControlOverrides {
+    fn set(&self, name: &str, value: &str) {
+        self.options
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.to_string(), value.to_string());
+    }
+
+    pub(crate) fn apply(&self, options: Options) -> Options {
+        self.options
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .fold(options, |options, (name, value)| options.set(name, value))
+    }
+}
+
+/// Cloneable handle that changes the rate control and GOP settings of a running encoder.
+///
+/// New values are applied to the shared codec context and picked up by the encoder on the next frame sent by the
+/// `EncoderPusher`. Bitrate and VBV changes only apply to encoders opened in a bitrate-driven mode, while CRF and QP
+/// changes only apply to encoders opened in the corresponding constant quality mode. Changed values are kept when
+/// the encoder is reopened.
+#[derive(Clone)]
+pub struct EncoderControl {
+    pub(super) codec_name: String,
+    pub(super) encode_context: Arc<Mutex<AVCodecContext>>,
+    pub(super) keyframe_requester: KeyframeRequester,
+    pub(super) keyframe_interval: Arc<AtomicU32>,
+    pub(super) rate_control_overrides: RateControlOverrides,
+}
+
+impl EncoderControl {
+    pub fn codec_name(&self) -> &str {
+        &self.codec_name
+    }
+
+    /// Out-of-band data of the open encoder, such as the parameter sets of encoders opened with a global header.
+    ///
+    /// The extradata changes when the encoder is reopened after a geometry change.
+    pub async fn extradata(&self) -> Option<Vec<u8>> {
+        extradata_of(&*self.encode_context.lock().await)
+    }
+
+    pub async fn set_bit_rate(&self, bit_rate: i64) -> Result<(), CodecError> {
+        self.check_reconfigurable(EncoderSetting::BitRate)?;
+        let mut encode_context = self.encode_context.lock().await;
+        unsafe {
+            encode_context.deref_mut().bit_rate = bit_rate;
+        }
+        self.rate_control_overrides.set("b", &bit_rate.to_string());
+        Ok(())
+    }
+
+    pub async fn set_max_rate(&self, max_rate: i64) -> Result<(), CodecError> {
+        self.check_reconfigurable(EncoderSetting::MaxRate)?;
+        let mut encode_context = self.encode_context.lock().await;
+        unsafe {
+            encode_context.deref_mut().rc_max_rate = max_rate;
+        }
+        self.rate_control_overrides
+            .set("maxrate", &max_rate.to_string());
+        Ok(())
+    }
+
+    pub async fn set_buffer_size(&self, buffer_size: i32) -> Result<(), CodecError> {
+        self.check_reconfigurable(EncoderSetting::BufferSize)?;
+        let mut encode_context = self.encode_context.lock().await;
+        unsafe {
+            encode_context.deref_mut().rc_buffer_size = buffer_size;
+        }
+        self.rate_control_overrides
+            .set("bufsize", &buffer_size.to_string());
+        Ok(())
+    }
+
+    pub async fn set_crf(&self, crf: f32) -> Result<(), CodecError> {
+        self.set_private_option(EncoderSetting::Crf, "crf", &crf.to_string())
+            .await
+    }
+
+    pub async fn set_qp(&self, qp: i32) -> Result<(), CodecError> {
+        self.set_private_option(EncoderSetting::Qp, "qp", &qp.to_string())
+            .await
+    }
+
+    /// Forces a keyframe at least every `interval` frames, `None` leaves the GOP to the encoder.
+    ///
+    /// Keyframes are forced on top of the GOP the encoder was opened with, which is left unchanged. The interval can
+    /// therefore shorten the GOP but not lengthen it, and `None` only stops forcing keyframes. Encoders needing longer
+    /// GOPs are to be built with the matching `g` option.
+    pub fn set_keyframe_interval(&self, interval: Option<u32>) {
+        self.keyframe_interval
+            .store(interval.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn request_keyframe(&self) {
+        self.keyframe_requester.request();
+    }
+
+    async fn set_private_option(&self, setting: EncoderSetting, name: &str, value: &str) -> Result<(), CodecError> {
+        self.check_reconfigurable(setting)?;
+
+        let option_name = option_c_string(name, name.to_string())?;
+        let option_value = option_c_string(name, value.to_string())?;
+
+        let mut encode_context = self.encode_context.lock().await;
+        let result = unsafe {
+            ffi::av_opt_set(
+                encode_context.as_mut_ptr().cast(),
+                option_name.as_ptr(),
+                option_value.as_ptr(),
+                ffi::AV_OPT_SEARCH_CHILDREN as i32,
+            )
+        };
+
+        if result < 0 {
+            return Err(CodecError::ReconfigurationFailed {
+                setting,
+                code: result,
+                message: av_error_message(result),
+            });
+        }
+
+        self.rate_control_overrides.set(name, value);
+        Ok(())
+    }
+
+    fn check_reconfigurable(&self, setting: EncoderSetting) -> Result<(), CodecError> {
+        if setting.is_reconfigurable(&self.codec_name) {
+            Ok(())
+        } else {
+            Err(CodecError::ReconfigurationUnsupported {
+                codec_name: self.codec_name.clone(),
+                setting,
+            })
+        }
+    }
+}
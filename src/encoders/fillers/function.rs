@@ -0,0 +1,27 @@
+use rsmpeg::avutil::AVFrame;
+
+use crate::error::CodecError;
+
+use super::AVFrameFiller;
+
+/// Fills frames through a closure, for one-off layouts which do not deserve a dedicated filler.
+///
+/// Plane rows can be written safely through `planes::PlanesMut`.
+pub struct FnFiller<C> {
+    pub(super) fill_fn: C,
+}
+
+impl<C> FnFiller<C> {
+    pub fn new(fill_fn: C) -> Self {
+        Self { fill_fn }
+    }
+}
+
+impl<F, C> AVFrameFiller<F> for FnFiller<C>
+where
+    C: FnMut(&F, &mut AVFrame) -> Result<(), CodecError>,
+{
+    fn fill(&mut self, frame_data: &F, avframe: &mut AVFrame) -> Result<(), CodecError> {
+        (self.fill_fn)(frame_data, avframe)
+    }
+}
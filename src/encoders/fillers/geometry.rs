@@ -0,0 +1,36 @@
+use rsmpeg::avutil::AVFrame;
+
+use crate::{error::CodecError, scaling::FrameGeometry};
+
+use super::AVFrameFiller;
+
+/// Wraps a filler with a closure telling the geometry of each source frame, so that the `EncoderPusher` rebuilds
+/// the scaler and the encoder on its own when the source changes size or format.
+pub struct GeometryAwareFiller<T, G> {
+    pub(super) filler: T,
+    pub(super) geometry_fn: G,
+}
+
+impl<T, G> GeometryAwareFiller<T, G> {
+    pub fn new(filler: T, geometry_fn: G) -> Self {
+        Self { filler, geometry_fn }
+    }
+}
+
+impl<F, T, G> AVFrameFiller<F> for GeometryAwareFiller<T, G>
+where
+    T: AVFrameFiller<F>,
+    G: Fn(&F) -> Option<FrameGeometry>,
+{
+    fn fill(&mut self, frame_data: &F, avframe: &mut AVFrame) -> Result<(), CodecError> {
+        self.filler.fill(frame_data, avframe)
+    }
+
+    fn packed_source<'a>(&self, frame_data: &'a F, geometry: FrameGeometry) -> Result<Option<&'a [u8]>, CodecError> {
+        self.filler.packed_source(frame_data, geometry)
+    }
+
+    fn source_geometry(&self, frame_data: &F) -> Option<FrameGeometry> {
+        (self.geometry_fn)(frame_data)
+    }
+}
@@ -1,8 +1,28 @@
 use rsmpeg::avutil::AVFrame;
 
+use crate::{error::CodecError, scaling::FrameGeometry};
+
+pub mod function;
+pub mod geometry;
+pub mod p010;
+pub mod packed;
 pub mod rgba;
 pub mod yuv420p;
 
 pub trait AVFrameFiller<F> {
-    fn fill(&mut self, frame_data: &F, avframe: &mut AVFrame);
-}
\ No newline at end of file
+    fn fill(&mut self, frame_data: &F, avframe: &mut AVFrame) -> Result<(), CodecError>;
+
+    /// Borrows the source buffer when it already holds the tightly packed planes of a `geometry` frame, so that the
+    /// scaler can read it in place instead of having it copied into the input frame first.
+    ///
+    /// Fillers returning `None`, as the default implementation does, always go through `fill`.
+    fn packed_source<'a>(&self, _frame_data: &'a F, _geometry: FrameGeometry) -> Result<Option<&'a [u8]>, CodecError> {
+        Ok(None)
+    }
+
+    /// Geometry of the source frame, used by the `EncoderPusher` to detect geometry changes when the frame does not
+    /// report it through `CodecFrame::frame_geometry`.
+    fn source_geometry(&self, _frame_data: &F) -> Option<FrameGeometry> {
+        None
+    }
+}
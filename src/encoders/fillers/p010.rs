@@ -0,0 +1,75 @@
+use remotia::{buffers::BytesMut, traits::BorrowFrameProperties};
+use rsmpeg::avutil::AVFrame;
+
+use crate::{error::CodecError, ffi, planes::PlanesMut, scaling::FrameGeometry};
+
+use super::{packed::PackedFrameFiller, AVFrameFiller};
+
+/// Fills P010 frames from the Y plane followed by the interleaved UV plane, with 16 bit little endian samples.
+///
+/// P010 stores the 10 significant bits in the high bits of each sample. Sources which store them in the low bits
+/// instead can be read with `from_lsb_samples`, shifting every sample while copying.
+pub struct P010FrameFiller<K> {
+    pub(super) packed: PackedFrameFiller<K>,
+    pub(super) lsb_samples: bool,
+}
+
+const P010_PIXEL_FORMATS: &[ffi::AVPixelFormat] = &[ffi::AVPixelFormat_AV_PIX_FMT_P010LE];
+
+impl<K> P010FrameFiller<K> {
+    pub fn new(p010_buffer_key: K) -> Self {
+        Self {
+            packed: PackedFrameFiller::with_pixel_formats(p010_buffer_key, P010_PIXEL_FORMATS),
+            lsb_samples: false,
+        }
+    }
+
+    pub fn from_lsb_samples(p010_buffer_key: K) -> Self {
+        Self {
+            packed: PackedFrameFiller::with_pixel_formats(p010_buffer_key, P010_PIXEL_FORMATS),
+            lsb_samples: true,
+        }
+    }
+
+    pub fn pixel_format(&self) -> ffi::AVPixelFormat {
+        ffi::AVPixelFormat_AV_PIX_FMT_P010LE
+    }
+}
+
+impl<F, K> AVFrameFiller<F> for P010FrameFiller<K>
+where
+    K: Send + Copy,
+    F: BorrowFrameProperties<K, BytesMut> + Send + 'static,
+{
+    fn fill(&mut self, frame_data: &F, avframe: &mut AVFrame) -> Result<(), CodecError> {
+        if !self.lsb_samples {
+            return self.packed.fill(frame_data, avframe);
+        }
+
+        self.packed.check_pixel_format(avframe.format)?;
+
+        let source_buffer = frame_data
+            .get_ref(&self.packed.buffer_key)
+            .ok_or(CodecError::MissingBuffer)?;
+
+        PlanesMut::new(avframe)?.copy_rows_from_packed(source_buffer, |destination_row, source_row| {
+            let samples = destination_row
+                .chunks_exact_mut(2)
+                .zip(source_row.chunks_exact(2));
+
+            for (destination_sample, source_sample) in samples {
+                let sample = u16::from_le_bytes([source_sample[0], source_sample[1]]) << 6;
+                destination_sample.copy_from_slice(&sample.to_le_bytes());
+            }
+        })
+    }
+
+    fn packed_source<'a>(&self, frame_data: &'a F, geometry: FrameGeometry) -> Result<Option<&'a [u8]>, CodecError> {
+        // Samples stored in the low bits must be shifted, which needs the copy
+        if self.lsb_samples {
+            return Ok(None);
+        }
+
+        self.packed.packed_source(frame_data, geometry)
+    }
+}
@@ -0,0 +1,128 @@
+use remotia::{buffers::BytesMut, traits::BorrowFrameProperties};
+use rsmpeg::avutil::AVFrame;
+
+use crate::{
+    error::CodecError,
+    ffi,
+    planes::{check_pixel_format, PlanesMut},
+    scaling::FrameGeometry,
+};
+
+use super::AVFrameFiller;
+
+/// Fills frames from a tightly packed buffer, holding each plane after the previous one with no line padding. Planes
+/// layout is taken from the pixel format descriptor of the filled frame.
+///
+/// Frames of any pixel format are accepted, unless the filler is restricted to a few of them, as the constructors
+/// named after a layout do.
+pub struct PackedFrameFiller<K> {
+    pub(super) buffer_key: K,
+    pub(super) pixel_formats: &'static [ffi::AVPixelFormat],
+}
+
+impl<K> PackedFrameFiller<K> {
+    pub fn new(buffer_key: K) -> Self {
+        Self::with_pixel_formats(buffer_key, &[])
+    }
+
+    /// Only accepts frames in one of `pixel_formats`, or in any format when empty.
+    pub fn with_pixel_formats(buffer_key: K, pixel_formats: &'static [ffi::AVPixelFormat]) -> Self {
+        Self {
+            buffer_key,
+            pixel_formats,
+        }
+    }
+
+    /// Packed 4 bytes per pixel frames in R, G, B, A byte order.
+    pub fn rgba(buffer_key: K) -> Self {
+        Self::with_pixel_formats(buffer_key, &[ffi::AVPixelFormat_AV_PIX_FMT_RGBA])
+    }
+
+    /// Packed 4 bytes per pixel frames in B, G, R, A byte order, as produced by X11 and DXGI captures.
+    pub fn bgra(buffer_key: K) -> Self {
+        Self::with_pixel_formats(buffer_key, &[ffi::AVPixelFormat_AV_PIX_FMT_BGRA])
+    }
+
+    /// Same as `bgra`, with the fourth byte treated as padding, which spares the encoder from an alpha channel that
+    /// captures never fill in.
+    pub fn bgr0(buffer_key: K) -> Self {
+        Self::with_pixel_formats(buffer_key, &[ffi::AVPixelFormat_AV_PIX_FMT_BGR0])
+    }
+
+    /// Single plane 8 bit grayscale frames.
+    pub fn gray8(buffer_key: K) -> Self {
+        Self::with_pixel_formats(buffer_key, &[ffi::AVPixelFormat_AV_PIX_FMT_GRAY8])
+    }
+
+    /// NV12 frames, with the Y plane followed by the interleaved UV plane, both at full row width.
+    pub fn nv12(buffer_key: K) -> Self {
+        Self::with_pixel_formats(buffer_key, &[ffi::AVPixelFormat_AV_PIX_FMT_NV12])
+    }
+
+    /// 10 bit planar 4:2:0 frames, with each sample stored in the low bits of a 16 bit little endian word.
+    pub fn yuv420p10(buffer_key: K) -> Self {
+        Self::with_pixel_formats(buffer_key, &[ffi::AVPixelFormat_AV_PIX_FMT_YUV420P10LE])
+    }
+
+    /// YUV420P frames, with the Y plane followed by the quarter resolution U and V planes.
+    pub fn yuv420p(buffer_key: K) -> Self {
+        Self::with_pixel_formats(
+            buffer_key,
+            &[
+                ffi::AVPixelFormat_AV_PIX_FMT_YUV420P,
+                ffi::AVPixelFormat_AV_PIX_FMT_YUVJ420P,
+            ],
+        )
+    }
+
+    /// YUV444P frames, with three full resolution planes.
+    pub fn yuv444p(buffer_key: K) -> Self {
+        Self::with_pixel_formats(
+            buffer_key,
+            &[
+                ffi::AVPixelFormat_AV_PIX_FMT_YUV444P,
+                ffi::AVPixelFormat_AV_PIX_FMT_YUVJ444P,
+            ],
+        )
+    }
+
+    /// First accepted pixel format, to be encoded from, or `None` when any format is accepted.
+    pub fn pixel_format(&self) -> Option<ffi::AVPixelFormat> {
+        self.pixel_formats.first().copied()
+    }
+
+    pub(super) fn check_pixel_format(&self, pixel_format: ffi::AVPixelFormat) -> Result<(), CodecError> {
+        if self.pixel_formats.is_empty() {
+            return Ok(());
+        }
+
+        check_pixel_format(pixel_format, self.pixel_formats)
+    }
+}
+
+impl<F, K> AVFrameFiller<F> for PackedFrameFiller<K>
+where
+    K: Send + Copy,
+    F: BorrowFrameProperties<K, BytesMut> + Send + 'static,
+{
+    fn fill(&mut self, frame_data: &F, avframe: &mut AVFrame) -> Result<(), CodecError> {
+        self.check_pixel_format(avframe.format)?;
+
+        let source_buffer = frame_data
+            .get_ref(&self.buffer_key)
+            .ok_or(CodecError::MissingBuffer)?;
+
+        // Row sizes come from the pixel format descriptor, so wide samples and odd chroma sizes are accounted for
+        PlanesMut::new(avframe)?.copy_from_packed(source_buffer)
+    }
+
+    fn packed_source<'a>(&self, frame_data: &'a F, geometry: FrameGeometry) -> Result<Option<&'a [u8]>, CodecError> {
+        self.check_pixel_format(geometry.pixel_format)?;
+
+        let source_buffer = frame_data
+            .get_ref(&self.buffer_key)
+            .ok_or(CodecError::MissingBuffer)?;
+
+        Ok(Some(source_buffer))
+    }
+}
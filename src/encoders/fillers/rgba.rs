@@ -1,15 +1,20 @@
-use remotia::{traits::BorrowFrameProperties, buffers::BytesMut};
+use remotia::{buffers::BytesMut, traits::BorrowFrameProperties};
 use rsmpeg::avutil::AVFrame;
 
-use super::AVFrameFiller;
+use crate::{error::CodecError, scaling::FrameGeometry};
 
+use super::{packed::PackedFrameFiller, AVFrameFiller};
+
+/// Fills RGBA frames from a tightly packed buffer, rejecting frames of any other pixel format.
 pub struct RGBAFrameFiller<K> {
-    pub(super) rgba_buffer_key: K,
+    pub(super) packed: PackedFrameFiller<K>,
 }
 
 impl<K> RGBAFrameFiller<K> {
     pub fn new(rgba_buffer_key: K) -> Self {
-        Self { rgba_buffer_key }
+        Self {
+            packed: PackedFrameFiller::rgba(rgba_buffer_key),
+        }
     }
 }
 
@@ -18,15 +23,11 @@ where
     K: Send + Copy,
     F: BorrowFrameProperties<K, BytesMut> + Send + 'static,
 {
-    fn fill(&mut self, frame_data:&F, avframe: &mut AVFrame) {
-        let source_buffer = frame_data.get_ref(&self.rgba_buffer_key).unwrap();
-
-        let linesize = avframe.linesize;
-        let height = avframe.height as usize;
-
-        let linesize = linesize[0] as usize;
-        let data = unsafe { std::slice::from_raw_parts_mut(avframe.data[0], height * linesize) };
+    fn fill(&mut self, frame_data: &F, avframe: &mut AVFrame) -> Result<(), CodecError> {
+        self.packed.fill(frame_data, avframe)
+    }
 
-        data.copy_from_slice(source_buffer);
+    fn packed_source<'a>(&self, frame_data: &'a F, geometry: FrameGeometry) -> Result<Option<&'a [u8]>, CodecError> {
+        self.packed.packed_source(frame_data, geometry)
     }
-}
\ No newline at end of file
+}
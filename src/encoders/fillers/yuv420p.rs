@@ -1,15 +1,20 @@
 use remotia::{buffers::BytesMut, traits::BorrowFrameProperties};
 use rsmpeg::avutil::AVFrame;
 
-use super::AVFrameFiller;
+use crate::{error::CodecError, scaling::FrameGeometry};
 
+use super::{packed::PackedFrameFiller, AVFrameFiller};
+
+/// Fills YUV420P frames from a tightly packed buffer, rejecting frames of any other pixel format.
 pub struct YUV420PFrameFiller<K> {
-    pub(super) yuv420p_buffer_key: K,
+    pub(super) packed: PackedFrameFiller<K>,
 }
 
 impl<K> YUV420PFrameFiller<K> {
     pub fn new(yuv420p_buffer_key: K) -> Self {
-        Self { yuv420p_buffer_key }
+        Self {
+            packed: PackedFrameFiller::yuv420p(yuv420p_buffer_key),
+        }
     }
 }
 
@@ -18,23 +23,11 @@ where
     K: Send + Copy,
     F: BorrowFrameProperties<K, BytesMut> + Send + 'static,
 {
-    fn fill(&mut self, frame_data: &F, avframe: &mut AVFrame) {
-        let source_buffer = frame_data.get_ref(&self.yuv420p_buffer_key).unwrap();
-
-        let linesize = avframe.linesize;
-        let height = avframe.height as usize;
-
-        let y_data = unsafe { std::slice::from_raw_parts_mut(avframe.data[0], height * linesize[0] as usize) };
-        let u_data = unsafe { std::slice::from_raw_parts_mut(avframe.data[1], (height / 2) * linesize[1] as usize) };
-        let v_data = unsafe { std::slice::from_raw_parts_mut(avframe.data[2], (height / 2) * linesize[2] as usize) };
-
-        let mut written_bytes = 0;
-        y_data.copy_from_slice(&source_buffer[..y_data.len()]);
-        written_bytes += y_data.len();
-
-        u_data.copy_from_slice(&source_buffer[written_bytes..written_bytes + u_data.len()]);
-        written_bytes += u_data.len();
+    fn fill(&mut self, frame_data: &F, avframe: &mut AVFrame) -> Result<(), CodecError> {
+        self.packed.fill(frame_data, avframe)
+    }
 
-        v_data.copy_from_slice(&source_buffer[written_bytes..written_bytes + v_data.len()]);
+    fn packed_source<'a>(&self, frame_data: &'a F, geometry: FrameGeometry) -> Result<Option<&'a [u8]>, CodecError> {
+        self.packed.packed_source(frame_data, geometry)
     }
 }
@@ -0,0 +1,114 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Arc,
+};
+
+/// Cloneable handle that forces the next frame sent by the `EncoderPusher` to be encoded as a keyframe.
+#[derive(Clone, Default)]
+pub struct KeyframeRequester {
+    requested: Arc<AtomicBool>,
+}
+
+impl KeyframeRequester {
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+    }
+
+    pub(super) fn take(&self) -> bool {
+        self.requested.swap(false, Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub(super) struct KeyframePolicy {
+    pub(super) requester: KeyframeRequester,
+
+    /// Maximum number of frames between two forced keyframes, zero when disabled
+    pub(super) interval: Arc<AtomicU32>,
+
+    frames_since_keyframe: u32,
+}
+
+impl KeyframePolicy {
+    pub fn should_force(&mut self, frame_requested: bool) -> bool {
+        let interval = self.interval.load(Ordering::Relaxed);
+        let interval_elapsed = interval > 0 && self.frames_since_keyframe + 1 >= interval;
+
+        let force = self.requester.take() || frame_requested || interval_elapsed;
+
+        if force {
+            self.frames_since_keyframe = 0;
+        } else {
+            self.frames_since_keyframe = self.frames_since_keyframe.saturating_add(1);
+        }
+
+        force
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(interval: u32) -> KeyframePolicy {
+        KeyframePolicy {
+            interval: Arc::new(AtomicU32::new(interval)),
+            ..Default::default()
+        }
+    }
+
+    fn forced_frames(policy: &mut KeyframePolicy, frames: usize) -> Vec<bool> {
+        (0..frames).map(|_| policy.should_force(false)).collect()
+    }
+
+    #[test]
+    fn leaves_keyframes_to_the_encoder_by_default() {
+        let mut policy = policy(0);
+
+        assert_eq!(forced_frames(&mut policy, 4), [false; 4]);
+    }
+
+    #[test]
+    fn forces_a_single_keyframe_per_request() {
+        let mut policy = policy(0);
+        let requester = policy.requester.clone();
+
+        requester.request();
+        requester.request();
+        assert_eq!(forced_frames(&mut policy, 3), [true, false, false]);
+    }
+
+    #[test]
+    fn forces_keyframes_requested_by_frames() {
+        let mut policy = policy(0);
+
+        assert!(policy.should_force(true));
+        assert!(!policy.should_force(false));
+    }
+
+    #[test]
+    fn forces_keyframes_at_the_interval() {
+        let mut policy = policy(3);
+
+        assert_eq!(forced_frames(&mut policy, 6), [false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn restarts_the_interval_from_requested_keyframes() {
+        let mut policy = policy(3);
+
+        assert!(!policy.should_force(false));
+        assert!(policy.should_force(true));
+        assert_eq!(forced_frames(&mut policy, 3), [false, false, true]);
+    }
+
+    #[test]
+    fn picks_up_interval_changes() {
+        let mut policy = policy(0);
+        let interval = policy.interval.clone();
+
+        assert_eq!(forced_frames(&mut policy, 2), [false, false]);
+        interval.store(2, Ordering::Relaxed);
+        assert_eq!(forced_frames(&mut policy, 2), [true, false]);
+    }
+}
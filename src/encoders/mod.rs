@@ -1,17 +1,30 @@
 use std::{ffi::CString, ptr::NonNull, sync::Arc};
 
-use rsmpeg::avcodec::{AVCodec, AVCodecContext};
+use rsmpeg::{
+    avcodec::{AVCodec, AVCodecContext},
+    UnsafeDerefMut,
+};
 
 use tokio::sync::Mutex;
 
-use crate::{builder::unwrap_mandatory, ffi, scaling::Scaler};
+use crate::{
+    builder::unwrap_mandatory,
+    error::CodecError,
+    ffi,
+    scaling::{FrameGeometry, ScalerBuilder},
+    timestamps::{TimestampMode, Timestamper, DEFAULT_FRAME_RATE, DEFAULT_TIME_BASE},
+};
 
 use super::options::Options;
 
+mod control;
 pub mod fillers;
+mod keyframes;
 mod puller;
 mod pusher;
 
+pub use control::*;
+pub use keyframes::*;
 pub use puller::*;
 pub use pusher::*;
 
@@ -19,7 +32,15 @@ pub struct EncoderBuilder<T> {
     codec_id: Option<String>,
     filler: Option<T>,
     options: Option<Options>,
-    scaler: Option<Scaler>,
+    scaler: Option<ScalerBuilder>,
+    width: Option<i32>,
+    height: Option<i32>,
+    pixel_format: Option<ffi::AVPixelFormat>,
+    time_base: Option<ffi::AVRational>,
+    frame_rate: Option<ffi::AVRational>,
+    timestamp_mode: Option<TimestampMode>,
+    single_copy: Option<bool>,
+    global_header: Option<bool>,
 }
 
 impl<T> Default for EncoderBuilder<T> {
@@ -35,56 +56,175 @@ impl<T> EncoderBuilder<T> {
             filler: None,
             options: None,
             scaler: None,
+            width: None,
+            height: None,
+            pixel_format: None,
+            time_base: None,
+            frame_rate: None,
+            timestamp_mode: None,
+            single_copy: None,
+            global_header: None,
         }
     }
 
     builder_set!(filler, T);
     builder_set!(options, Options);
-    builder_set!(scaler, Scaler);
+    builder_set!(scaler, ScalerBuilder);
+    builder_set!(width, i32);
+    builder_set!(height, i32);
+    builder_set!(pixel_format, ffi::AVPixelFormat);
+    builder_set!(time_base, ffi::AVRational);
+    builder_set!(frame_rate, ffi::AVRational);
+    builder_set!(timestamp_mode, TimestampMode);
+
+    // When enabled, fillers able to lend their source buffer have it read in place by the scaler, so that each frame
+    // is copied once, either by the scaler or straight into the encoded frame when no scaling is needed. The encoder
+    // always gets a frame of its own, since it may still hold it once the source buffer is back to its pool
+    builder_set!(single_copy, bool);
+
+    // Moves the parameter sets out of the stream into the extradata, as expected by MP4 and MKV muxers. The
+    // extradata is available from `EncoderControl::extradata` once the encoder has been built
+    builder_set!(global_header, bool);
 
     pub fn codec_id(mut self, codec_id: &str) -> Self {
         self.codec_id = Some(codec_id.to_string());
         self
     }
 
-    pub fn build(self) -> (EncoderPusher<T>, EncoderPuller) {
-        let codec_id = unwrap_mandatory(self.codec_id);
-        let options = self.options.unwrap_or_default();
-
-        let scaler = unwrap_mandatory(self.scaler);
-
-        let encode_context = {
-            let codec_id_string = CString::new(codec_id).unwrap();
-            let encoder = AVCodec::find_encoder_by_name(&codec_id_string).unwrap();
-            let mut encode_context = AVCodecContext::new(&encoder);
-            encode_context.set_width(scaler.scaled_frame().width);
-            encode_context.set_height(scaler.scaled_frame().height);
-            encode_context.set_pix_fmt(scaler.scaled_frame().format);
-            encode_context.set_time_base(ffi::AVRational { num: 1, den: 60 * 1000 });
-            encode_context.set_framerate(ffi::AVRational { num: 60, den: 1 });
-            let mut encode_context = unsafe {
-                let raw_encode_context = encode_context.into_raw().as_ptr();
-                AVCodecContext::from_raw(NonNull::new(raw_encode_context).unwrap())
-            };
+    pub fn build(self) -> Result<(EncoderPusher<T>, EncoderPuller), CodecError> {
+        let codec_id = unwrap_mandatory(self.codec_id, "codec_id")?;
+
+        let filler = unwrap_mandatory(self.filler, "filler")?;
+        let encoder = find_encoder(&codec_id)?;
+
+        // Encoded width, height and pixel format default to the scaler input ones, frames are passed through unscaled
+        // when no scaler is set
+        let mut scaler_builder = self.scaler.unwrap_or_default();
+        scaler_builder.output_width = scaler_builder.output_width.or(self.width);
+        scaler_builder.output_height = scaler_builder.output_height.or(self.height);
+        let input_width = scaler_builder.input_width.or(scaler_builder.output_width);
+        let input_height = scaler_builder.input_height.or(scaler_builder.output_height);
+        scaler_builder.input_width = Some(unwrap_mandatory(input_width, "width")?);
+        scaler_builder.input_height = Some(unwrap_mandatory(input_height, "height")?);
+
+        let pixel_format = match self.pixel_format.or(scaler_builder.output_pixel_format) {
+            Some(pixel_format) => pixel_format,
+            None => negotiate_pixel_format(&encoder, scaler_builder.input_pixel_format)?,
+        };
+        scaler_builder.output_pixel_format = Some(pixel_format);
+        scaler_builder.input_pixel_format = scaler_builder.input_pixel_format.or(Some(pixel_format));
 
-            let options_dict = options.to_av_dict();
+        let scaler = scaler_builder.build()?;
 
-            encode_context.open(Some(options_dict)).unwrap();
+        let time_base = self.time_base.unwrap_or(DEFAULT_TIME_BASE);
+        let timestamper = Timestamper::new(self.timestamp_mode.unwrap_or_default(), time_base);
 
-            Arc::new(Mutex::new(encode_context))
+        let config = EncoderConfig {
+            codec_id: codec_id.clone(),
+            options: self.options.unwrap_or_default(),
+            rate_control_overrides: RateControlOverrides::default(),
+            time_base,
+            frame_rate: self.frame_rate.unwrap_or(DEFAULT_FRAME_RATE),
+            global_header: self.global_header.unwrap_or(false),
         };
 
-        let filler = unwrap_mandatory(self.filler);
+        let encode_context = Arc::new(Mutex::new(config.open(scaler.output_geometry())?));
+        let pending_packets = PendingPackets::default();
 
-        (
+        Ok((
             EncoderPusher {
+                codec_name: codec_id.clone(),
                 encode_context: encode_context.clone(),
+                pending_packets: pending_packets.clone(),
+                config,
                 scaler,
                 filler,
+                timestamper: timestamper.clone(),
+                keyframes: keyframes::KeyframePolicy::default(),
+                single_copy: self.single_copy.unwrap_or(false),
             },
             EncoderPuller {
+                codec_name: codec_id,
                 encode_context: encode_context.clone(),
+                pending_packets,
+                timestamper,
             },
-        )
+        ))
+    }
+}
+
+/// Everything needed to open the encode context again when the encoded geometry changes.
+pub(crate) struct EncoderConfig {
+    codec_id: String,
+    options: Options,
+    rate_control_overrides: RateControlOverrides,
+    time_base: ffi::AVRational,
+    frame_rate: ffi::AVRational,
+    global_header: bool,
+}
+
+impl EncoderConfig {
+    pub(crate) fn open(&self, geometry: FrameGeometry) -> Result<AVCodecContext, CodecError> {
+        let encoder = find_encoder(&self.codec_id)?;
+        let mut encode_context = AVCodecContext::new(&encoder);
+        encode_context.set_width(geometry.width);
+        encode_context.set_height(geometry.height);
+        encode_context.set_pix_fmt(geometry.pixel_format);
+        encode_context.set_time_base(self.time_base);
+        encode_context.set_framerate(self.frame_rate);
+        if self.global_header {
+            unsafe {
+                encode_context.deref_mut().flags |= ffi::AV_CODEC_FLAG_GLOBAL_HEADER as i32;
+            }
+        }
+        let mut encode_context = unsafe {
+            let raw_encode_context = encode_context.into_raw().as_ptr();
+            AVCodecContext::from_raw(NonNull::new(raw_encode_context).unwrap())
+        };
+
+        let options_dict = self
+            .rate_control_overrides
+            .apply(self.options.clone())
+            .to_av_dict()?;
+
+        encode_context
+            .open(Some(options_dict))
+            .map_err(CodecError::open_failed)?;
+
+        Ok(encode_context)
+    }
+}
+
+fn find_encoder(codec_id: &str) -> Result<AVCodec, CodecError> {
+    let codec_id_string = CString::new(codec_id).map_err(|_| CodecError::CodecNotFound(codec_id.to_string()))?;
+    AVCodec::find_encoder_by_name(&codec_id_string).ok_or_else(|| CodecError::CodecNotFound(codec_id.to_string()))
+}
+
+/// Picks the pixel format supported by the encoder which loses the least information from the source format, or the
+/// first supported one when the source format is unknown.
+fn negotiate_pixel_format(
+    encoder: &AVCodec,
+    source_pixel_format: Option<ffi::AVPixelFormat>,
+) -> Result<ffi::AVPixelFormat, CodecError> {
+    let supported_pixel_formats = encoder.pix_fmts;
+
+    if supported_pixel_formats.is_null() {
+        return source_pixel_format.ok_or(CodecError::MissingField("pixel_format"));
     }
+
+    let pixel_format = match source_pixel_format {
+        Some(source_pixel_format) => unsafe {
+            ffi::avcodec_find_best_pix_fmt_of_list(
+                supported_pixel_formats,
+                source_pixel_format,
+                0,
+                std::ptr::null_mut(),
+            )
+        },
+        None => unsafe { *supported_pixel_formats },
+    };
+
+    log::debug!("Negotiated pixel format {} for encoder", pixel_format);
+
+    Ok(pixel_format)
 }
@@ -1,16 +1,45 @@
-use std::sync::Arc;
+use std::{collections::VecDeque, sync::Arc};
 
 use remotia::traits::{FrameError, FrameProcessor};
-use rsmpeg::{avcodec::AVCodecContext, error::RsmpegError};
+use rsmpeg::{
+    avcodec::{AVCodecContext, AVPacket},
+    error::RsmpegError,
+};
 
 use async_trait::async_trait;
 
 use tokio::sync::Mutex;
 
-use crate::FFMpegCodec;
+use crate::{
+    error::{CodecError, CodecErrorKind, CodecErrorReport},
+    ffi,
+    packets::packet_data,
+    timestamps::Timestamper,
+    EncodedPacketSink, PacketMetadata,
+};
+
+/// Packet drained out of an encode context replaced after a geometry change, waiting to be pulled.
+pub(crate) struct PendingPacket {
+    data: Vec<u8>,
+    metadata: PacketMetadata,
+}
+
+impl PendingPacket {
+    pub(crate) fn copy_of(packet: &AVPacket) -> Self {
+        Self {
+            data: packet_data(packet).to_vec(),
+            metadata: packet_metadata(packet),
+        }
+    }
+}
+
+pub(crate) type PendingPackets = Arc<Mutex<VecDeque<PendingPacket>>>;
 
 pub struct EncoderPuller {
+    pub(super) codec_name: String,
     pub(super) encode_context: Arc<Mutex<AVCodecContext>>,
+    pub(super) pending_packets: PendingPackets,
+    pub(super) timestamper: Timestamper,
 }
 
 impl EncoderPuller {
@@ -20,14 +49,63 @@ impl EncoderPuller {
             flush_error,
         }
     }
+
+    fn write_packet<F>(
+        &self,
+        frame_data: &mut F,
+        data: &[u8],
+        metadata: PacketMetadata,
+        written_bytes: &mut usize,
+    ) -> Result<(), CodecError>
+    where
+        F: EncodedPacketSink,
+    {
+        frame_data.set_frame_id(self.timestamper.frame_id(metadata.pts));
+        frame_data.write_packet_data(data)?;
+        frame_data.write_packet_metadata(PacketMetadata {
+            offset: *written_bytes,
+            ..metadata
+        });
+
+        *written_bytes += data.len();
+        Ok(())
+    }
+
+    fn report_missing_packet_buffer<F>(&self, mut frame_data: F) -> F
+    where
+        F: EncodedPacketSink,
+    {
+        log::warn!(
+            "Unable to write the packets of frame {}: missing packet buffer",
+            frame_data.get_frame_id()
+        );
+        frame_data.report_codec_error(CodecErrorReport::missing_packet_buffer(&self.codec_name));
+        frame_data
+    }
 }
 
 #[async_trait]
 impl<F> FrameProcessor<F> for EncoderPuller
 where
-    F: FFMpegCodec + Send + 'static,
+    F: EncodedPacketSink + Send + 'static,
 {
     async fn process(&mut self, mut frame_data: F) -> Option<F> {
+        let mut written_bytes = 0;
+
+        // Packets of a replaced encode context come before the ones of the current context
+        let pending_packets = std::mem::take(&mut *self.pending_packets.lock().await);
+        for pending_packet in pending_packets {
+            let write_result = self.write_packet(
+                &mut frame_data,
+                &pending_packet.data,
+                pending_packet.metadata,
+                &mut written_bytes,
+            );
+            if write_result.is_err() {
+                return Some(self.report_missing_packet_buffer(frame_data));
+            }
+        }
+
         loop {
             let mut encode_context = self.encode_context.lock().await;
 
@@ -45,18 +123,43 @@ where
                     frame_data.report_flush_error();
                     break;
                 }
-                Err(e) => panic!("{:?}", e),
+                Err(error) => {
+                    log::warn!("Unhandled codec error during packet receive: {}", error);
+                    frame_data.report_codec_error(CodecErrorReport::from_rsmpeg(
+                        CodecErrorKind::ReceivePacket,
+                        &error,
+                        &self.codec_name,
+                    ));
+                    break;
+                }
             };
 
-            let data = unsafe { std::slice::from_raw_parts(packet.data, packet.size as usize) };
-
-            frame_data.set_frame_id(packet.pts);
-            frame_data.write_packet_data(data);
+            let write_result = self.write_packet(
+                &mut frame_data,
+                packet_data(&packet),
+                packet_metadata(&packet),
+                &mut written_bytes,
+            );
+            if write_result.is_err() {
+                return Some(self.report_missing_packet_buffer(frame_data));
+            }
         }
         Some(frame_data)
     }
 }
 
+/// Describes an encoded packet, as if it were the first one written to the frame.
+fn packet_metadata(packet: &AVPacket) -> PacketMetadata {
+    PacketMetadata {
+        keyframe: packet.flags & ffi::AV_PKT_FLAG_KEY as i32 != 0,
+        pts: packet.pts,
+        dts: packet.dts,
+        duration: packet.duration,
+        offset: 0,
+        size: packet_data(packet).len(),
+    }
+}
+
 pub struct EncoderFlusher<E> {
     pub(super) encode_context: Arc<Mutex<AVCodecContext>>,
     pub(crate) flush_error: E,
@@ -72,7 +175,9 @@ where
         if let Some(error) = frame_data.get_error() {
             if error == self.flush_error {
                 log::debug!("Received flush error, flushing encode context...");
-                self.encode_context.lock().await.send_frame(None).unwrap();
+                if let Err(error) = self.encode_context.lock().await.send_frame(None) {
+                    log::warn!("Unable to flush encode context: {}", error);
+                }
             }
         }
 
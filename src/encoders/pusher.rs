@@ -1,50 +1,235 @@
-use std::sync::Arc;
+use std::{collections::VecDeque, sync::Arc};
 
 use remotia::traits::FrameProcessor;
-use rsmpeg::avcodec::AVCodecContext;
+use rsmpeg::{avcodec::AVCodecContext, UnsafeDerefMut};
 
 use async_trait::async_trait;
 
 use tokio::sync::Mutex;
 
-use crate::{scaling::Scaler, FFMpegCodec};
+use crate::{
+    error::{CodecError, CodecErrorKind, CodecErrorReport},
+    ffi,
+    scaling::{FrameGeometry, Scaler},
+    timestamps::Timestamper,
+    CodecFrame,
+};
 
-use super::fillers::AVFrameFiller;
+use super::{
+    fillers::AVFrameFiller, keyframes::KeyframePolicy, EncoderConfig, EncoderControl, KeyframeRequester, PendingPacket,
+    PendingPackets,
+};
 
 pub struct EncoderPusher<T> {
+    pub(super) codec_name: String,
     pub(super) encode_context: Arc<Mutex<AVCodecContext>>,
+    pub(super) pending_packets: PendingPackets,
+    pub(super) config: EncoderConfig,
     pub(super) scaler: Scaler,
     pub(super) filler: T,
+    pub(super) timestamper: Timestamper,
+    pub(super) keyframes: KeyframePolicy,
+    pub(super) single_copy: bool,
+}
+
+impl<T> EncoderPusher<T> {
+    pub fn keyframe_requester(&self) -> KeyframeRequester {
+        self.keyframes.requester.clone()
+    }
+
+    pub fn control(&self) -> EncoderControl {
+        EncoderControl {
+            codec_name: self.codec_name.clone(),
+            encode_context: self.encode_context.clone(),
+            keyframe_requester: self.keyframes.requester.clone(),
+            keyframe_interval: self.keyframes.interval.clone(),
+            rate_control_overrides: self.config.rate_control_overrides.clone(),
+        }
+    }
 }
 
 #[async_trait]
 impl<F, T> FrameProcessor<F> for EncoderPusher<T>
 where
     T: AVFrameFiller<F> + Send,
-    F: FFMpegCodec + Send + 'static,
+    F: CodecFrame + Send + 'static,
 {
     async fn process(&mut self, mut frame_data: F) -> Option<F> {
         let mut encode_context = self.encode_context.lock().await;
 
-        let input_avframe = self.scaler.input_frame_mut();
-        self.filler.fill(&frame_data, input_avframe);
+        let source_geometry = frame_data
+            .frame_geometry()
+            .or_else(|| self.filler.source_geometry(&frame_data));
+        if let Some(geometry) = source_geometry {
+            if geometry != self.scaler.input_geometry() {
+                log::info!("Input geometry changed to {:?}, reconfiguring the encoder", geometry);
 
-        self.scaler.scale();
-        self.scaler
-            .scaled_frame_mut()
-            .set_pts(frame_data.get_frame_id());
+                let mut pending_packets = self.pending_packets.lock().await;
+                match reconfigure(
+                    &mut self.scaler,
+                    &self.config,
+                    &mut encode_context,
+                    &mut pending_packets,
+                    geometry,
+                    &mut frame_data,
+                    &self.codec_name,
+                ) {
+                    Ok(reopened) => {
+                        if reopened {
+                            self.keyframes.requester.request();
+                        }
+                        frame_data.report_geometry_change(self.scaler.output_geometry());
+                    }
+                    Err(error) => {
+                        log::warn!("Unable to reconfigure the encoder: {}", error);
+                        frame_data.report_codec_error(CodecErrorReport::from_codec_error(
+                            CodecErrorKind::Reconfiguration,
+                            &error,
+                            &self.codec_name,
+                        ));
+                        return Some(frame_data);
+                    }
+                }
+            }
+        }
+
+        let fill_result = fill_and_scale(
+            &mut self.filler,
+            &mut self.scaler,
+            &frame_data,
+            self.single_copy,
+            &self.codec_name,
+        );
+        if let Err(report) = fill_result {
+            frame_data.report_codec_error(report);
+            return Some(frame_data);
+        }
+
+        let pts = self
+            .timestamper
+            .frame_pts(frame_data.get_frame_id(), frame_data.capture_instant());
+        self.scaler.scaled_frame_mut().set_pts(pts);
+
+        let keyframe_requested = frame_data.is_keyframe_requested();
+        let picture_type = if self.keyframes.should_force(keyframe_requested) {
+            log::debug!("Forcing keyframe for frame {}", frame_data.get_frame_id());
+            ffi::AVPictureType_AV_PICTURE_TYPE_I
+        } else {
+            ffi::AVPictureType_AV_PICTURE_TYPE_NONE
+        };
+        unsafe {
+            self.scaler.scaled_frame_mut().deref_mut().pict_type = picture_type;
+        }
 
         let send_result = encode_context.send_frame(Some(self.scaler.scaled_frame()));
 
         if let Err(error) = send_result {
-            match error {
-                err => {
-                    log::warn!("Unhandled codec error during frame send: {}", err);
-                    frame_data.report_codec_error();
-                },
-            }
+            log::warn!("Unhandled codec error during frame send: {}", error);
+            frame_data.report_codec_error(CodecErrorReport::from_rsmpeg(
+                CodecErrorKind::SendFrame,
+                &error,
+                &self.codec_name,
+            ));
         }
 
         Some(frame_data)
     }
 }
+
+/// Fills the scaler input frame and scales it. In single copy mode, the source buffer is scaled in place whenever the
+/// filler is able to lend it, or copied straight into the encoded frame when no scaling is needed.
+fn fill_and_scale<F, T>(
+    filler: &mut T,
+    scaler: &mut Scaler,
+    frame_data: &F,
+    single_copy: bool,
+    codec_name: &str,
+) -> Result<(), CodecErrorReport>
+where
+    T: AVFrameFiller<F>,
+{
+    if single_copy {
+        let packed_source = filler
+            .packed_source(frame_data, scaler.input_geometry())
+            .map_err(|error| {
+                log::warn!("Unable to borrow the source buffer: {}", error);
+                CodecErrorReport::from_codec_error(CodecErrorKind::Fill, &error, codec_name)
+            })?;
+
+        if let Some(source) = packed_source {
+            return scaler.scale_packed(source).map_err(|error| {
+                log::warn!("Unable to scale the source buffer: {}", error);
+                CodecErrorReport::from_codec_error(CodecErrorKind::Scaling, &error, codec_name)
+            });
+        }
+    }
+
+    filler
+        .fill(frame_data, scaler.input_frame_mut())
+        .map_err(|error| {
+            log::warn!("Unable to fill the input frame: {}", error);
+            CodecErrorReport::from_codec_error(CodecErrorKind::Fill, &error, codec_name)
+        })?;
+
+    scaler.scale().map_err(|error| {
+        log::warn!("Unable to scale the input frame: {}", error);
+        CodecErrorReport::from_rsmpeg(CodecErrorKind::Scaling, &error, codec_name)
+    })
+}
+
+/// Adapts the scaler to a new input geometry and reopens the encode context if the encoded geometry changed too. The
+/// packets still held by the previous context are queued for the `EncoderPuller`, even when it cannot be flushed, in
+/// which case the flush error is reported on the frame.
+///
+/// Returns whether the encode context has been reopened.
+fn reconfigure<F>(
+    scaler: &mut Scaler,
+    config: &EncoderConfig,
+    encode_context: &mut AVCodecContext,
+    pending_packets: &mut VecDeque<PendingPacket>,
+    input_geometry: FrameGeometry,
+    frame_data: &mut F,
+    codec_name: &str,
+) -> Result<bool, CodecError>
+where
+    F: CodecFrame,
+{
+    let previous_input_geometry = scaler.input_geometry();
+    let previous_output_geometry = scaler.output_geometry();
+    scaler.rebuild(input_geometry)?;
+
+    let output_geometry = scaler.output_geometry();
+    if output_geometry == previous_output_geometry {
+        return Ok(false);
+    }
+
+    let new_encode_context = match config.open(output_geometry) {
+        Ok(new_encode_context) => new_encode_context,
+        Err(error) => {
+            // Keep the scaler consistent with the encode context which is still in use
+            scaler.rebuild(previous_input_geometry)?;
+            return Err(error);
+        }
+    };
+
+    // Drain the old context, since its packets cannot be pulled from it after the swap
+    let flush_result = encode_context.send_frame(None);
+    let drained_packets = pending_packets.len();
+    while let Ok(packet) = encode_context.receive_packet() {
+        pending_packets.push_back(PendingPacket::copy_of(&packet));
+    }
+    log::debug!("Queued {} pending packets", pending_packets.len() - drained_packets);
+
+    if let Err(error) = flush_result {
+        log::warn!("Unable to flush the replaced encode context: {}", error);
+        frame_data.report_codec_error(CodecErrorReport::from_rsmpeg(
+            CodecErrorKind::Reconfiguration,
+            &error,
+            codec_name,
+        ));
+    }
+
+    *encode_context = new_encode_context;
+
+    Ok(true)
+}
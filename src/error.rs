@@ -0,0 +1,203 @@
+use std::{ffi::CStr, fmt};
+
+use rsmpeg::error::RsmpegError;
+
+use crate::{encoders::EncoderSetting, ffi};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodecError {
+    MissingField(&'static str),
+    CodecNotFound(String),
+    ParserNotFound(String),
+    OpenFailed {
+        code: i32,
+        message: String,
+    },
+    ScalerContextFailed,
+    ScalingFailed {
+        code: i32,
+        message: String,
+    },
+    UnsupportedPixelFormat(ffi::AVPixelFormat),
+    MissingBuffer,
+    BufferSizeMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    FrameAllocationFailed {
+        code: i32,
+        message: String,
+    },
+    ReconfigurationUnsupported {
+        codec_name: String,
+        setting: EncoderSetting,
+    },
+    ReconfigurationFailed {
+        setting: EncoderSetting,
+        code: i32,
+        message: String,
+    },
+    BitstreamFilterNotFound(String),
+    MalformedBitstream(&'static str),
+    BitstreamFilterFailed {
+        code: i32,
+        message: String,
+    },
+    MtuTooSmall(usize),
+    InvalidOption(String),
+}
+
+impl CodecError {
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            Self::OpenFailed { code, .. }
+            | Self::ScalingFailed { code, .. }
+            | Self::FrameAllocationFailed { code, .. }
+            | Self::ReconfigurationFailed { code, .. }
+            | Self::BitstreamFilterFailed { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn open_failed(error: RsmpegError) -> Self {
+        let code = error.raw_error().unwrap_or(ffi::AVERROR_UNKNOWN);
+        Self::OpenFailed {
+            code,
+            message: av_error_message(code),
+        }
+    }
+
+    pub(crate) fn scaling_failed(code: i32) -> Self {
+        Self::ScalingFailed {
+            code,
+            message: av_error_message(code),
+        }
+    }
+
+    pub(crate) fn bitstream_filter_failed(code: i32) -> Self {
+        Self::BitstreamFilterFailed {
+            code,
+            message: av_error_message(code),
+        }
+    }
+
+    pub(crate) fn frame_allocation_failed(error: RsmpegError) -> Self {
+        let code = error.raw_error().unwrap_or(ffi::AVERROR_UNKNOWN);
+        Self::FrameAllocationFailed {
+            code,
+            message: av_error_message(code),
+        }
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingField(field) => write!(f, "missing mandatory field '{}'", field),
+            Self::CodecNotFound(codec_id) => write!(f, "codec '{}' not found", codec_id),
+            Self::ParserNotFound(codec_id) => write!(f, "no parser available for codec '{}'", codec_id),
+            Self::OpenFailed { code, message } => write!(f, "unable to open codec context: {} ({})", message, code),
+            Self::ScalerContextFailed => write!(f, "unable to create the scaling context"),
+            Self::ScalingFailed { code, message } => write!(f, "unable to scale the frame: {} ({})", message, code),
+            Self::UnsupportedPixelFormat(pixel_format) => write!(f, "unsupported pixel format {}", pixel_format),
+            Self::MissingBuffer => write!(f, "missing frame data buffer"),
+            Self::BufferSizeMismatch { expected, actual } => {
+                write!(f, "buffer size mismatch: expected {} bytes, found {}", expected, actual)
+            }
+            Self::FrameAllocationFailed { code, message } => {
+                write!(f, "unable to allocate frame buffers: {} ({})", message, code)
+            }
+            Self::ReconfigurationUnsupported { codec_name, setting } => {
+                write!(f, "codec '{}' cannot change {:?} at runtime", codec_name, setting)
+            }
+            Self::ReconfigurationFailed { setting, code, message } => {
+                write!(f, "unable to change {:?}: {} ({})", setting, message, code)
+            }
+            Self::BitstreamFilterNotFound(filters) => write!(f, "invalid bitstream filter chain '{}'", filters),
+            Self::MalformedBitstream(reason) => write!(f, "malformed bitstream: {}", reason),
+            Self::BitstreamFilterFailed { code, message } => {
+                write!(f, "unable to initialize the bitstream filter: {} ({})", message, code)
+            }
+            Self::MtuTooSmall(mtu) => write!(f, "MTU of {} bytes is too small for RTP packets", mtu),
+            Self::InvalidOption(key) => write!(f, "option '{}' holds a NUL byte", key),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodecErrorKind {
+    SendFrame,
+    ReceivePacket,
+    SendPacket,
+    ReceiveFrame,
+    DecoderFlushed,
+    Scaling,
+    Reconfiguration,
+    Fill,
+    Extract,
+    BitstreamFilter,
+    Payload,
+    PacketLoss,
+    LateFrame,
+    MissingPacketBuffer,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodecErrorReport {
+    pub kind: CodecErrorKind,
+    pub code: Option<i32>,
+    pub codec_name: String,
+}
+
+impl CodecErrorReport {
+    pub fn new(kind: CodecErrorKind, code: Option<i32>, codec_name: &str) -> Self {
+        Self {
+            kind,
+            code,
+            codec_name: codec_name.to_string(),
+        }
+    }
+
+    pub(crate) fn from_rsmpeg(kind: CodecErrorKind, error: &RsmpegError, codec_name: &str) -> Self {
+        Self::new(kind, error.raw_error(), codec_name)
+    }
+
+    pub(crate) fn from_codec_error(kind: CodecErrorKind, error: &CodecError, codec_name: &str) -> Self {
+        Self::new(kind, error.code(), codec_name)
+    }
+
+    pub(crate) fn missing_packet_buffer(codec_name: &str) -> Self {
+        Self::new(CodecErrorKind::MissingPacketBuffer, None, codec_name)
+    }
+
+    pub fn message(&self) -> Option<String> {
+        self.code.map(av_error_message)
+    }
+}
+
+impl fmt::Display for CodecErrorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} error in codec '{}'", self.kind, self.codec_name)?;
+
+        if let Some(code) = self.code {
+            write!(f, ": {} ({})", av_error_message(code), code)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn av_error_message(code: i32) -> String {
+    let mut buffer = [0 as std::os::raw::c_char; ffi::AV_ERROR_MAX_STRING_SIZE as usize];
+    let result = unsafe { ffi::av_strerror(code, buffer.as_mut_ptr(), buffer.len()) };
+
+    if result < 0 {
+        return format!("unknown error {}", code);
+    }
+
+    unsafe { CStr::from_ptr(buffer.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,33 @@
+use rsmpeg::avcodec::AVCodecContext;
+
+use crate::ffi;
+
+/// Copies the extradata of a codec context, when there is any.
+pub(crate) fn extradata_of(context: &AVCodecContext) -> Option<Vec<u8>> {
+    if context.extradata.is_null() || context.extradata_size <= 0 {
+        return None;
+    }
+
+    let extradata = unsafe { std::slice::from_raw_parts(context.extradata, context.extradata_size as usize) };
+    Some(extradata.to_vec())
+}
+
+/// Allocates a copy of the extradata to be owned by a codec context or by codec parameters, which free it on their
+/// own. Returns `None` when the allocation fails.
+pub(crate) fn alloc_extradata(extradata: &[u8]) -> Option<*mut u8> {
+    // FFmpeg readers expect zeroed padding past the end of the extradata
+    let buffer = unsafe { ffi::av_mallocz(extradata.len() + ffi::AV_INPUT_BUFFER_PADDING_SIZE as usize) as *mut u8 };
+    if buffer.is_null() {
+        return None;
+    }
+
+    unsafe { std::ptr::copy_nonoverlapping(extradata.as_ptr(), buffer, extradata.len()) };
+    Some(buffer)
+}
+
+/// Tells whether the extradata is an avcC or hvcC decoder configuration record rather than Annex B parameter sets,
+/// meaning that packets are length-prefixed instead of being delimited by start codes.
+pub(crate) fn is_length_prefixed_config(extradata: &[u8]) -> bool {
+    // Configuration records start with their version, which is always 1, while Annex B starts with a zero byte
+    extradata.first() == Some(&1)
+}
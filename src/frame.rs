@@ -0,0 +1,92 @@
+use std::time::Instant;
+
+use crate::{
+    error::{CodecError, CodecErrorReport},
+    scaling::FrameGeometry,
+};
+
+/// Describes one of the encoded packets written through `EncodedPacketSink::write_packet_data`.
+///
+/// `offset` and `size` locate the packet bytes, with offsets counted from the first packet written during the same
+/// pipeline step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketMetadata {
+    pub keyframe: bool,
+    pub pts: i64,
+    pub dts: i64,
+    pub duration: i64,
+    pub offset: usize,
+    pub size: usize,
+}
+
+pub trait CodecFrame {
+    fn set_frame_id(&mut self, frame_id: i64);
+    fn get_frame_id(&self) -> i64;
+    fn report_codec_error(&mut self, report: CodecErrorReport);
+
+    /// Forces the encoder to produce a keyframe out of this frame.
+    fn is_keyframe_requested(&self) -> bool {
+        false
+    }
+
+    /// Time at which the raw frame was captured, used by `TimestampMode::WallClock` instead of the time at which the
+    /// frame reaches the encoder.
+    fn capture_instant(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Geometry of the raw frame pushed to the encoder, when it may change during the stream. When `None`, the
+    /// encoder falls back to the geometry given by its filler, if any.
+    fn frame_geometry(&self) -> Option<FrameGeometry> {
+        None
+    }
+
+    /// Called when the encoder or the decoder switches to a new output geometry, starting from this frame.
+    fn report_geometry_change(&mut self, _geometry: FrameGeometry) {}
+
+    /// Called by the `JitterBufferPuller` on the first frame released after giving up on missing frames.
+    fn report_frame_loss(&mut self, _lost_frames: u64) {}
+
+    /// Frames given up on right before this one, as reported through `report_frame_loss`. From such a frame on, the
+    /// `DecoderPusher` drops frames until the next keyframe.
+    fn reported_frame_loss(&self) -> u64 {
+        0
+    }
+}
+
+pub trait EncodedPacketSink: CodecFrame {
+    /// Appends to the packet buffer, failing with `CodecError::MissingBuffer` when the frame has none. The processor
+    /// writing the packets reports the error under its own codec name.
+    fn write_packet_data(&mut self, packet_data: &[u8]) -> Result<(), CodecError>;
+    fn report_flush_error(&mut self);
+
+    /// Empties the packet buffer, if any, so that processors rewriting packets can replace its content.
+    fn clear_packet_data(&mut self);
+
+    fn write_packet_metadata(&mut self, _metadata: PacketMetadata) {}
+
+    /// Drops the `PacketMetadata` written so far, along with the packets they describe when the packet buffer is
+    /// cleared.
+    fn clear_packet_metadata(&mut self) {}
+}
+
+pub trait EncodedPacketSource: CodecFrame {
+    /// Encoded packets of the frame, or `None` when the frame carries no packet buffer at all.
+    fn get_packet_data_buffer(&self) -> Option<&[u8]>;
+
+    /// Whether the packets of the frame hold a keyframe, as told by the `PacketMetadata` of the processor which wrote
+    /// them. When `None`, processors needing it inspect the bitstream instead.
+    fn contains_keyframe(&self) -> Option<bool> {
+        None
+    }
+}
+
+/// Decoded frames are written by the `AVFrameExtractor` of the `DecoderPuller`.
+pub trait DecodedFrameSink: CodecFrame {
+    fn report_decoder_drain_error(&mut self);
+}
+
+/// Shorthand for frame data that takes part in both the encoding and the decoding side of a pipeline.
+pub trait FFMpegCodec: EncodedPacketSink + EncodedPacketSource + DecodedFrameSink {}
+
+impl<T> FFMpegCodec for T where T: EncodedPacketSink + EncodedPacketSource + DecodedFrameSink {}
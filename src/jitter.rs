@@ -0,0 +1,425 @@
+//! Reordering of received frames by id, ahead of the decoder.
+//!
+//! The `JitterBufferPusher` holds the incoming frames, and the `JitterBufferPuller` releases them one per step in
+//! increasing id order. The puller is meant to be driven by a ticker faster than the frame rate, in a pipeline of its
+//! own: frames held while waiting for missing ones are then caught up with, and held frames are released even when
+//! no more frames come in. The frames fed to the puller only pace it and are dropped.
+//!
+//! The first frame is held for the latency budget, in case frames preceding it arrive out of order. Then, frames
+//! following the last released one are released right away. When some frames are missing, the following ones are
+//! held until the gap is filled or the oldest held frame has waited for the latency budget. The missing frames are
+//! then considered lost, and the next released frame reports how many of them were given up on through
+//! `CodecFrame::report_frame_loss`.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use remotia::traits::FrameProcessor;
+
+use async_trait::async_trait;
+
+use crate::{
+    error::{CodecErrorKind, CodecErrorReport},
+    ffi,
+    timestamps::{rescale, DEFAULT_FRAME_RATE, DEFAULT_TIME_BASE},
+    CodecFrame, EncodedPacketSink,
+};
+
+const DEFAULT_LATENCY: Duration = Duration::from_millis(50);
+const COMPONENT_NAME: &str = "jitter_buffer";
+
+pub struct JitterBufferBuilder {
+    latency: Option<Duration>,
+    frame_rate: Option<ffi::AVRational>,
+    time_base: Option<ffi::AVRational>,
+}
+
+impl Default for JitterBufferBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JitterBufferBuilder {
+    pub fn new() -> Self {
+        Self {
+            latency: None,
+            frame_rate: None,
+            time_base: None,
+        }
+    }
+
+    // How long a frame may wait for the missing frames preceding it, 50 ms by default
+    builder_set!(latency, Duration);
+
+    // Rate of the received frames, 60 fps by default. Along with the time base, it gives the difference between the
+    // ids of consecutive frames, hence the gaps left by missing frames
+    builder_set!(frame_rate, ffi::AVRational);
+
+    // Time base of the frame ids, as given to the `RtpDepayloader`. Frame ids are milliseconds in
+    // `TimestampMode::WallClock`
+    builder_set!(time_base, ffi::AVRational);
+
+    pub fn build<F>(self) -> (JitterBufferPusher<F>, JitterBufferPuller<F>) {
+        let frame_rate = self.frame_rate.unwrap_or(DEFAULT_FRAME_RATE);
+        let frame_duration = ffi::AVRational {
+            num: frame_rate.den,
+            den: frame_rate.num,
+        };
+        let frame_id_interval = rescale(1, frame_duration, self.time_base.unwrap_or(DEFAULT_TIME_BASE)).max(1);
+
+        let state = Arc::new(Mutex::new(JitterState::new(
+            self.latency.unwrap_or(DEFAULT_LATENCY),
+            frame_id_interval,
+        )));
+
+        (
+            JitterBufferPusher { state: state.clone() },
+            JitterBufferPuller {
+                state,
+                lost_frames_counter: LostFramesCounter::default(),
+            },
+        )
+    }
+}
+
+/// Cloneable handle counting the frames given up on by the jitter buffer.
+#[derive(Clone, Default)]
+pub struct LostFramesCounter {
+    lost_frames: Arc<AtomicU64>,
+}
+
+impl LostFramesCounter {
+    pub fn get(&self) -> u64 {
+        self.lost_frames.load(Ordering::Relaxed)
+    }
+
+    fn add(&self, lost_frames: u64) {
+        self.lost_frames.fetch_add(lost_frames, Ordering::Relaxed);
+    }
+}
+
+struct HeldFrame<F> {
+    arrival: Instant,
+    frame_data: F,
+}
+
+struct JitterState<F> {
+    latency: Duration,
+    frame_id_interval: i64,
+    held_frames: BTreeMap<i64, HeldFrame<F>>,
+    last_released_frame_id: Option<i64>,
+    pending_lost_frames: u64,
+}
+
+type SharedJitterState<F> = Arc<Mutex<JitterState<F>>>;
+
+fn lock<F>(state: &SharedJitterState<F>) -> MutexGuard<'_, JitterState<F>> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<F> JitterState<F> {
+    fn new(latency: Duration, frame_id_interval: i64) -> Self {
+        Self {
+            latency,
+            frame_id_interval,
+            held_frames: BTreeMap::new(),
+            last_released_frame_id: None,
+            pending_lost_frames: 0,
+        }
+    }
+}
+
+impl<F> JitterState<F>
+where
+    F: CodecFrame,
+{
+    fn is_late(&self, frame_id: i64) -> bool {
+        let already_released = self
+            .last_released_frame_id
+            .is_some_and(|last_released_frame_id| frame_id <= last_released_frame_id);
+
+        already_released || self.held_frames.contains_key(&frame_id)
+    }
+
+    /// Holds a frame until its turn comes, giving it back when it arrived too late or twice.
+    fn hold(&mut self, frame_data: F) -> Result<(), F> {
+        let frame_id = frame_data.get_frame_id();
+        if self.is_late(frame_id) {
+            return Err(frame_data);
+        }
+
+        self.held_frames.insert(
+            frame_id,
+            HeldFrame {
+                arrival: Instant::now(),
+                frame_data,
+            },
+        );
+
+        Ok(())
+    }
+
+    fn release_next(&mut self, lost_frames_counter: &LostFramesCounter) -> Option<F> {
+        let frame_id = *self.held_frames.first_key_value()?.0;
+
+        // Rounded, so that slightly irregular ids are not taken for gaps
+        let missing_frames = match self.last_released_frame_id {
+            Some(last_released_frame_id) => {
+                (frame_id - last_released_frame_id + self.frame_id_interval / 2) / self.frame_id_interval - 1
+            }
+            None => 0,
+        };
+
+        if missing_frames > 0 || self.last_released_frame_id.is_none() {
+            let oldest_arrival = self
+                .held_frames
+                .values()
+                .map(|held_frame| held_frame.arrival)
+                .min()?;
+            if oldest_arrival.elapsed() < self.latency {
+                log::trace!(
+                    "Waiting for {} missing frames before frame {}",
+                    missing_frames,
+                    frame_id
+                );
+                return None;
+            }
+        }
+
+        if missing_frames > 0 {
+            log::debug!("Gave up on {} missing frames before frame {}", missing_frames, frame_id);
+            self.pending_lost_frames += missing_frames as u64;
+            lost_frames_counter.add(missing_frames as u64);
+        }
+
+        let (_, held_frame) = self.held_frames.pop_first()?;
+        let mut frame_data = held_frame.frame_data;
+
+        if self.pending_lost_frames > 0 {
+            frame_data.report_frame_loss(self.pending_lost_frames);
+            self.pending_lost_frames = 0;
+        }
+
+        self.last_released_frame_id = Some(frame_id);
+        Some(frame_data)
+    }
+}
+
+/// Holds the incoming frames until the `JitterBufferPuller` releases them, hence never passes them on.
+///
+/// Frames arriving after a later one has been released, or twice, are passed on instead, with their packets cleared
+/// and a `LateFrame` error.
+pub struct JitterBufferPusher<F> {
+    state: SharedJitterState<F>,
+}
+
+#[async_trait]
+impl<F> FrameProcessor<F> for JitterBufferPusher<F>
+where
+    F: EncodedPacketSink + Send + 'static,
+{
+    async fn process(&mut self, frame_data: F) -> Option<F> {
+        let mut late_frame = lock(&self.state).hold(frame_data).err()?;
+
+        log::debug!("Frame {} arrived too late", late_frame.get_frame_id());
+        late_frame.clear_packet_data();
+        late_frame.clear_packet_metadata();
+        late_frame.report_codec_error(CodecErrorReport::new(CodecErrorKind::LateFrame, None, COMPONENT_NAME));
+
+        Some(late_frame)
+    }
+}
+
+/// Releases the next held frame at every step, if its turn has come, in place of the frame it is fed.
+pub struct JitterBufferPuller<F> {
+    state: SharedJitterState<F>,
+    lost_frames_counter: LostFramesCounter,
+}
+
+impl<F> JitterBufferPuller<F> {
+    pub fn lost_frames_counter(&self) -> LostFramesCounter {
+        self.lost_frames_counter.clone()
+    }
+
+    /// Number of frames currently held.
+    pub fn len(&self) -> usize {
+        lock(&self.state).held_frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        lock(&self.state).held_frames.is_empty()
+    }
+}
+
+#[async_trait]
+impl<F> FrameProcessor<F> for JitterBufferPuller<F>
+where
+    F: CodecFrame + Send + 'static,
+{
+    async fn process(&mut self, _frame_data: F) -> Option<F> {
+        lock(&self.state).release_next(&self.lost_frames_counter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    const FRAME_ID_INTERVAL: i64 = 10;
+    const LATENCY: Duration = Duration::from_millis(20);
+
+    struct TestFrame {
+        frame_id: i64,
+        lost_frames: u64,
+    }
+
+    impl CodecFrame for TestFrame {
+        fn set_frame_id(&mut self, frame_id: i64) {
+            self.frame_id = frame_id;
+        }
+
+        fn get_frame_id(&self) -> i64 {
+            self.frame_id
+        }
+
+        fn report_codec_error(&mut self, _report: CodecErrorReport) {}
+
+        fn report_frame_loss(&mut self, lost_frames: u64) {
+            self.lost_frames = lost_frames;
+        }
+    }
+
+    fn frame(index: i64) -> TestFrame {
+        TestFrame {
+            frame_id: index * FRAME_ID_INTERVAL,
+            lost_frames: 0,
+        }
+    }
+
+    fn release_all(state: &mut JitterState<TestFrame>, lost_frames_counter: &LostFramesCounter) -> Vec<i64> {
+        std::iter::from_fn(|| state.release_next(lost_frames_counter))
+            .map(|frame_data| frame_data.frame_id)
+            .collect()
+    }
+
+    /// Releases the first frame, once it has been held for the latency budget.
+    fn start(state: &mut JitterState<TestFrame>, lost_frames_counter: &LostFramesCounter, index: i64) {
+        assert!(state.hold(frame(index)).is_ok());
+        assert!(state.release_next(lost_frames_counter).is_none());
+
+        thread::sleep(LATENCY);
+        assert_eq!(release_all(state, lost_frames_counter), [index * FRAME_ID_INTERVAL]);
+    }
+
+    #[test]
+    fn derives_frame_id_interval_from_frame_rate() {
+        let (pusher, _) = JitterBufferBuilder::new()
+            .frame_rate(ffi::AVRational { num: 30, den: 1 })
+            .time_base(ffi::AVRational { num: 1, den: 1000 })
+            .build::<TestFrame>();
+
+        assert_eq!(lock(&pusher.state).frame_id_interval, 33);
+    }
+
+    #[test]
+    fn reorders_frames() {
+        let mut state = JitterState::new(LATENCY, FRAME_ID_INTERVAL);
+        let lost_frames_counter = LostFramesCounter::default();
+
+        start(&mut state, &lost_frames_counter, 0);
+
+        assert!(state.hold(frame(2)).is_ok());
+        assert!(state.release_next(&lost_frames_counter).is_none());
+
+        assert!(state.hold(frame(1)).is_ok());
+        assert_eq!(release_all(&mut state, &lost_frames_counter), [10, 20]);
+        assert_eq!(lost_frames_counter.get(), 0);
+    }
+
+    #[test]
+    fn gives_up_on_missing_frames() {
+        let mut state = JitterState::new(LATENCY, FRAME_ID_INTERVAL);
+        let lost_frames_counter = LostFramesCounter::default();
+
+        start(&mut state, &lost_frames_counter, 0);
+
+        assert!(state.hold(frame(3)).is_ok());
+        assert!(state.hold(frame(4)).is_ok());
+        assert!(state.release_next(&lost_frames_counter).is_none());
+
+        thread::sleep(LATENCY);
+
+        let released_frame = state.release_next(&lost_frames_counter).unwrap();
+        assert_eq!((released_frame.frame_id, released_frame.lost_frames), (30, 2));
+        assert_eq!(lost_frames_counter.get(), 2);
+
+        // Input has paused, the held frames are still released
+        let released_frame = state.release_next(&lost_frames_counter).unwrap();
+        assert_eq!((released_frame.frame_id, released_frame.lost_frames), (40, 0));
+        assert!(state.held_frames.is_empty());
+    }
+
+    #[test]
+    fn gives_back_late_and_duplicate_frames() {
+        let mut state = JitterState::new(LATENCY, FRAME_ID_INTERVAL);
+        let lost_frames_counter = LostFramesCounter::default();
+
+        start(&mut state, &lost_frames_counter, 1);
+
+        assert!(state.hold(frame(0)).is_err());
+        assert!(state.hold(frame(1)).is_err());
+
+        assert!(state.hold(frame(3)).is_ok());
+        assert!(state.hold(frame(3)).is_err());
+        assert_eq!(state.held_frames.len(), 1);
+    }
+
+    #[test]
+    fn holds_the_first_frame() {
+        let mut state = JitterState::new(LATENCY, FRAME_ID_INTERVAL);
+        let lost_frames_counter = LostFramesCounter::default();
+
+        assert!(state.hold(frame(1)).is_ok());
+        assert!(state.release_next(&lost_frames_counter).is_none());
+
+        // Reordered at the start of the stream
+        assert!(state.hold(frame(0)).is_ok());
+        assert!(state.release_next(&lost_frames_counter).is_none());
+
+        thread::sleep(LATENCY);
+
+        assert_eq!(release_all(&mut state, &lost_frames_counter), [0, 10]);
+        assert_eq!(lost_frames_counter.get(), 0);
+    }
+
+    #[test]
+    fn catches_up_after_loss() {
+        let mut state = JitterState::new(LATENCY, FRAME_ID_INTERVAL);
+        let lost_frames_counter = LostFramesCounter::default();
+
+        let mut released_frames = Vec::new();
+        for index in (0..40).filter(|&index| index != 5) {
+            assert!(state.hold(frame(index)).is_ok());
+
+            // The puller ticks twice as fast as frames arrive
+            for _ in 0..2 {
+                released_frames.extend(state.release_next(&lost_frames_counter));
+            }
+
+            thread::sleep(Duration::from_millis(2));
+        }
+
+        assert!(state.held_frames.is_empty());
+        assert_eq!(released_frames.len(), 39);
+        assert_eq!(lost_frames_counter.get(), 1);
+    }
+}
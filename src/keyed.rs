@@ -0,0 +1,157 @@
+use std::time::Instant;
+
+use remotia::{
+    buffers::{BufMut, BytesMut},
+    traits::{BorrowFrameProperties, BorrowMutFrameProperties, FrameError},
+};
+
+use crate::{
+    error::{CodecError, CodecErrorReport},
+    frame::{CodecFrame, DecodedFrameSink, EncodedPacketSink, EncodedPacketSource, PacketMetadata},
+    scaling::FrameGeometry,
+};
+
+pub enum ReportedError {
+    Codec(CodecErrorReport),
+    EncoderFlushed,
+    DecoderDrained,
+}
+
+/// Implements the codec frame traits on top of the frame properties of `F`.
+///
+/// Encoded packets are appended to the buffer stored at `PACKET_BUFFER_KEY`, and every error is converted with
+/// `map_error` before being reported through `FrameError`. Packets written to a frame without that buffer are
+/// reported as a `MissingPacketBuffer` error by the processor writing them.
+pub trait KeyedCodecFrame {
+    type Key;
+    type Error;
+
+    const PACKET_BUFFER_KEY: Self::Key;
+
+    fn frame_id(&self) -> i64;
+    fn frame_id_mut(&mut self) -> &mut i64;
+    fn map_error(error: ReportedError) -> Self::Error;
+
+    fn on_packet_metadata(&mut self, _metadata: PacketMetadata) {}
+
+    fn on_packet_metadata_cleared(&mut self) {}
+
+    fn packet_keyframe(&self) -> Option<bool> {
+        None
+    }
+
+    fn requests_keyframe(&self) -> bool {
+        false
+    }
+
+    fn captured_at(&self) -> Option<Instant> {
+        None
+    }
+
+    fn raw_frame_geometry(&self) -> Option<FrameGeometry> {
+        None
+    }
+
+    fn on_geometry_change(&mut self, _geometry: FrameGeometry) {}
+
+    fn on_frame_loss(&mut self, _lost_frames: u64) {}
+
+    fn lost_frames(&self) -> u64 {
+        0
+    }
+}
+
+impl<F> CodecFrame for F
+where
+    F: KeyedCodecFrame + FrameError<F::Error>,
+{
+    fn set_frame_id(&mut self, frame_id: i64) {
+        *self.frame_id_mut() = frame_id;
+    }
+
+    fn get_frame_id(&self) -> i64 {
+        self.frame_id()
+    }
+
+    fn report_codec_error(&mut self, report: CodecErrorReport) {
+        self.report_error(F::map_error(ReportedError::Codec(report)));
+    }
+
+    fn is_keyframe_requested(&self) -> bool {
+        self.requests_keyframe()
+    }
+
+    fn capture_instant(&self) -> Option<Instant> {
+        self.captured_at()
+    }
+
+    fn frame_geometry(&self) -> Option<FrameGeometry> {
+        self.raw_frame_geometry()
+    }
+
+    fn report_geometry_change(&mut self, geometry: FrameGeometry) {
+        self.on_geometry_change(geometry);
+    }
+
+    fn report_frame_loss(&mut self, lost_frames: u64) {
+        self.on_frame_loss(lost_frames);
+    }
+
+    fn reported_frame_loss(&self) -> u64 {
+        self.lost_frames()
+    }
+}
+
+impl<F> EncodedPacketSink for F
+where
+    F: KeyedCodecFrame + FrameError<F::Error> + BorrowMutFrameProperties<F::Key, BytesMut>,
+{
+    fn write_packet_data(&mut self, packet_data: &[u8]) -> Result<(), CodecError> {
+        let packet_buffer = self
+            .get_mut_ref(&F::PACKET_BUFFER_KEY)
+            .ok_or(CodecError::MissingBuffer)?;
+        packet_buffer.put(packet_data);
+        Ok(())
+    }
+
+    fn report_flush_error(&mut self) {
+        self.report_error(F::map_error(ReportedError::EncoderFlushed));
+    }
+
+    fn clear_packet_data(&mut self) {
+        if let Some(packet_buffer) = self.get_mut_ref(&F::PACKET_BUFFER_KEY) {
+            packet_buffer.clear();
+        }
+    }
+
+    fn write_packet_metadata(&mut self, metadata: PacketMetadata) {
+        self.on_packet_metadata(metadata);
+    }
+
+    fn clear_packet_metadata(&mut self) {
+        self.on_packet_metadata_cleared();
+    }
+}
+
+impl<F> EncodedPacketSource for F
+where
+    F: KeyedCodecFrame + FrameError<F::Error> + BorrowFrameProperties<F::Key, BytesMut>,
+{
+    fn get_packet_data_buffer(&self) -> Option<&[u8]> {
+        self.get_ref(&F::PACKET_BUFFER_KEY)
+            .map(|packet_buffer| &packet_buffer[..])
+    }
+
+    fn contains_keyframe(&self) -> Option<bool> {
+        self.packet_keyframe()
+    }
+}
+
+impl<F> DecodedFrameSink for F
+where
+    F: KeyedCodecFrame + FrameError<F::Error>,
+{
+    fn report_decoder_drain_error(&mut self) {
+        self.report_error(F::map_error(ReportedError::DecoderDrained));
+    }
+}
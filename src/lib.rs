@@ -1,20 +1,22 @@
 #[macro_use]
 mod builder;
 
+pub mod bitstream;
+pub mod bsf;
 pub mod decoders;
 pub mod encoders;
+pub mod error;
+pub mod jitter;
+pub mod keyed;
 pub mod scaling;
 pub mod options;
+pub mod planes;
+pub mod rtp;
+pub mod timestamps;
 
-pub use rsmpeg::ffi;
+mod extradata;
+mod frame;
+mod packets;
 
-pub trait FFMpegCodec {
-    fn write_packet_data(&mut self, packet_data: &[u8]);
-    fn get_packet_data_buffer(&self) -> &[u8];
-    fn write_decoded_buffer(&mut self, data: &[u8]);
-    fn report_flush_error(&mut self);
-    fn report_codec_error(&mut self);
-    fn report_decoder_drain_error(&mut self);
-    fn set_frame_id(&mut self, frame_id: i64);
-    fn get_frame_id(&self) -> i64;
-}
+pub use frame::*;
+pub use rsmpeg::ffi;
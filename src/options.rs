@@ -4,6 +4,8 @@ use rsmpeg::avutil::AVDictionary;
 
 use cstr::cstr;
 
+use crate::error::CodecError;
+
 #[derive(Default, Clone)]
 pub struct Options {
     pairs: HashMap<String, (String, u32)>,
@@ -25,17 +27,20 @@ impl Options {
         self
     }
 
-    pub fn to_av_dict(self) -> AVDictionary {
+    /// Fails with `InvalidOption` when a key or a value holds a NUL byte.
+    pub fn to_av_dict(self) -> Result<AVDictionary, CodecError> {
         let mut dict = AVDictionary::new(cstr!(""), cstr!(""), 0);
 
         for (key, (value, flags)) in self.pairs {
-            dict = dict.set(
-                &CString::new(key).unwrap(),
-                &CString::new(value).unwrap(),
-                flags,
-            );
+            let value = option_c_string(&key, value)?;
+            let key = option_c_string(&key, key.clone())?;
+            dict = dict.set(&key, &value, flags);
         }
 
-        dict
+        Ok(dict)
     }
 }
+
+pub(crate) fn option_c_string(key: &str, text: String) -> Result<CString, CodecError> {
+    CString::new(text).map_err(|_| CodecError::InvalidOption(key.to_string()))
+}
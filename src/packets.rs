@@ -0,0 +1,29 @@
+use rsmpeg::{avcodec::AVPacket, error::RsmpegError};
+
+use crate::ffi;
+
+/// Allocates a reference counted packet holding a copy of `data`.
+pub(crate) fn packet_from_slice(data: &[u8]) -> Result<AVPacket, RsmpegError> {
+    let mut packet = AVPacket::new();
+    let result = unsafe { ffi::av_new_packet(packet.as_mut_ptr(), data.len() as i32) };
+    if result < 0 {
+        return Err(RsmpegError::AVError(result));
+    }
+
+    if !data.is_empty() {
+        unsafe {
+            std::slice::from_raw_parts_mut(packet.data, data.len()).copy_from_slice(data);
+        }
+    }
+
+    Ok(packet)
+}
+
+/// Borrows the data of a packet, which may be missing for empty packets.
+pub(crate) fn packet_data(packet: &AVPacket) -> &[u8] {
+    if packet.data.is_null() || packet.size <= 0 {
+        return &[];
+    }
+
+    unsafe { std::slice::from_raw_parts(packet.data, packet.size as usize) }
+}
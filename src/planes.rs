@@ -0,0 +1,463 @@
+use remotia::buffers::{BufMut, BytesMut};
+use rsmpeg::{avutil::AVFrame, error::RsmpegError};
+
+use crate::{error::CodecError, ffi};
+
+/// Size of the meaningful bytes of a picture plane, without any line padding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PlaneLayout {
+    pub width: usize,
+    pub row_bytes: usize,
+    pub height: usize,
+}
+
+pub(crate) fn plane_layouts(
+    pixel_format: ffi::AVPixelFormat,
+    width: i32,
+    height: i32,
+) -> Result<Vec<PlaneLayout>, CodecError> {
+    let descriptor = unsafe { ffi::av_pix_fmt_desc_get(pixel_format).as_ref() }
+        .ok_or(CodecError::UnsupportedPixelFormat(pixel_format))?;
+
+    let planes_count = unsafe { ffi::av_pix_fmt_count_planes(pixel_format) };
+    if planes_count <= 0 {
+        return Err(CodecError::UnsupportedPixelFormat(pixel_format));
+    }
+
+    (0..planes_count)
+        .map(|plane| {
+            let row_bytes = unsafe { ffi::av_image_get_linesize(pixel_format, width, plane) };
+            if row_bytes < 0 {
+                return Err(CodecError::UnsupportedPixelFormat(pixel_format));
+            }
+
+            // Only the chroma planes are subsampled, alpha keeps the luma size
+            let (plane_width, plane_height) = if plane == 1 || plane == 2 {
+                (
+                    subsampled(width, descriptor.log2_chroma_w),
+                    subsampled(height, descriptor.log2_chroma_h),
+                )
+            } else {
+                (width, height)
+            };
+
+            Ok(PlaneLayout {
+                width: plane_width as usize,
+                row_bytes: row_bytes as usize,
+                height: plane_height as usize,
+            })
+        })
+        .collect()
+}
+
+/// Divides a luma size by the chroma subsampling factor, rounding odd sizes up.
+fn subsampled(size: i32, log2_factor: u8) -> i32 {
+    let shift = log2_factor as i32;
+    (size + (1 << shift) - 1) >> shift
+}
+
+pub(crate) fn packed_size(layouts: &[PlaneLayout]) -> usize {
+    layouts
+        .iter()
+        .map(|layout| layout.row_bytes * layout.height)
+        .sum()
+}
+
+pub(crate) fn check_pixel_format(
+    pixel_format: ffi::AVPixelFormat,
+    accepted: &[ffi::AVPixelFormat],
+) -> Result<(), CodecError> {
+    if accepted.contains(&pixel_format) {
+        Ok(())
+    } else {
+        Err(CodecError::UnsupportedPixelFormat(pixel_format))
+    }
+}
+
+/// Read-only view over the rows of an AVFrame plane, as laid out by the pixel format descriptor.
+pub struct Plane<'a> {
+    data: &'a [u8],
+    stride: usize,
+    layout: PlaneLayout,
+}
+
+impl Plane<'_> {
+    /// Width in samples, which accounts for chroma subsampling.
+    pub fn width(&self) -> usize {
+        self.layout.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.layout.height
+    }
+
+    /// Bytes of each row holding pixel data, the rest of the stride being padding.
+    pub fn row_bytes(&self) -> usize {
+        self.layout.row_bytes
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn row(&self, index: usize) -> Option<&[u8]> {
+        self.rows().nth(index)
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        let row_bytes = self.layout.row_bytes;
+        self.data
+            .chunks(self.stride)
+            .take(self.layout.height)
+            .map(move |row| &row[..row_bytes])
+    }
+}
+
+/// Mutable view over the rows of an AVFrame plane, as laid out by the pixel format descriptor.
+pub struct PlaneMut<'a> {
+    data: &'a mut [u8],
+    stride: usize,
+    layout: PlaneLayout,
+}
+
+impl PlaneMut<'_> {
+    /// Width in samples, which accounts for chroma subsampling.
+    pub fn width(&self) -> usize {
+        self.layout.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.layout.height
+    }
+
+    /// Bytes of each row holding pixel data, the rest of the stride being padding.
+    pub fn row_bytes(&self) -> usize {
+        self.layout.row_bytes
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn row_mut(&mut self, index: usize) -> Option<&mut [u8]> {
+        self.rows_mut().nth(index)
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [u8]> {
+        let row_bytes = self.layout.row_bytes;
+        self.data
+            .chunks_mut(self.stride)
+            .take(self.layout.height)
+            .map(move |row| &mut row[..row_bytes])
+    }
+}
+
+/// Read-only views over all the planes of an AVFrame, sized after its pixel format, width and height.
+///
+/// Every plane must lie within one of the buffers referenced by the frame, otherwise the views are not created.
+pub struct Planes<'a> {
+    planes: Vec<Plane<'a>>,
+}
+
+impl<'a> Planes<'a> {
+    pub fn new(avframe: &'a AVFrame) -> Result<Self, CodecError> {
+        let layouts = plane_layouts(avframe.format, avframe.width, avframe.height)?;
+
+        let planes = layouts
+            .into_iter()
+            .enumerate()
+            .map(|(index, layout)| {
+                let stride = checked_stride(avframe, index, &layout)?;
+                let data = if layout.height == 0 {
+                    Default::default()
+                } else {
+                    let plane_size = checked_plane_size(avframe, index, stride, &layout)?;
+                    unsafe { std::slice::from_raw_parts(avframe.data[index], plane_size) }
+                };
+
+                Ok(Plane { data, stride, layout })
+            })
+            .collect::<Result<_, CodecError>>()?;
+
+        Ok(Self { planes })
+    }
+
+    pub fn len(&self) -> usize {
+        self.planes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.planes.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Plane<'a>> {
+        self.planes.get(index)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Plane<'a>> {
+        self.planes.iter()
+    }
+
+    /// Size of the picture with its planes stored one after the other and no line padding.
+    pub fn packed_size(&self) -> usize {
+        self.planes
+            .iter()
+            .map(|plane| plane.row_bytes() * plane.height())
+            .sum()
+    }
+
+    /// Appends the planes to a buffer, tightly packed with the line padding dropped, growing the buffer if needed.
+    pub fn copy_to_packed(&self, destination: &mut BytesMut) {
+        destination.reserve(self.packed_size());
+
+        for row in self.planes.iter().flat_map(|plane| plane.rows()) {
+            destination.put_slice(row);
+        }
+    }
+}
+
+/// Mutable views over all the planes of an AVFrame, sized after its pixel format, width and height.
+///
+/// Every plane must lie within one of the buffers referenced by the frame, otherwise the views are not created. Frames
+/// whose buffers are shared, for instance with an encoder still holding the previous picture, are given buffers of
+/// their own first.
+pub struct PlanesMut<'a> {
+    planes: Vec<PlaneMut<'a>>,
+}
+
+impl<'a> PlanesMut<'a> {
+    pub fn new(avframe: &'a mut AVFrame) -> Result<Self, CodecError> {
+        let layouts = plane_layouts(avframe.format, avframe.width, avframe.height)?;
+
+        let result = unsafe { ffi::av_frame_make_writable(avframe.as_mut_ptr()) };
+        if result < 0 {
+            return Err(CodecError::frame_allocation_failed(RsmpegError::AVError(result)));
+        }
+
+        let planes = layouts
+            .into_iter()
+            .enumerate()
+            .map(|(index, layout)| {
+                let stride = checked_stride(avframe, index, &layout)?;
+                let data = if layout.height == 0 {
+                    Default::default()
+                } else {
+                    // Planes never overlap, so each one can be borrowed mutably on its own
+                    let plane_size = checked_plane_size(avframe, index, stride, &layout)?;
+                    unsafe { std::slice::from_raw_parts_mut(avframe.data[index], plane_size) }
+                };
+
+                Ok(PlaneMut { data, stride, layout })
+            })
+            .collect::<Result<_, CodecError>>()?;
+
+        Ok(Self { planes })
+    }
+
+    pub fn len(&self) -> usize {
+        self.planes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.planes.is_empty()
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut PlaneMut<'a>> {
+        self.planes.get_mut(index)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut PlaneMut<'a>> {
+        self.planes.iter_mut()
+    }
+
+    /// Size of the picture with its planes stored one after the other and no line padding.
+    pub fn packed_size(&self) -> usize {
+        self.planes
+            .iter()
+            .map(|plane| plane.row_bytes() * plane.height())
+            .sum()
+    }
+
+    /// Copies a tightly packed picture, with planes stored one after the other, into the padded planes.
+    pub fn copy_from_packed(&mut self, source: &[u8]) -> Result<(), CodecError> {
+        self.copy_rows_from_packed(source, |destination_row, source_row| {
+            destination_row.copy_from_slice(source_row)
+        })
+    }
+
+    /// Same as `copy_from_packed`, with each row converted by `copy_row` instead of being copied as it is.
+    pub fn copy_rows_from_packed(
+        &mut self,
+        source: &[u8],
+        mut copy_row: impl FnMut(&mut [u8], &[u8]),
+    ) -> Result<(), CodecError> {
+        let expected_size = self.packed_size();
+        if source.len() != expected_size {
+            return Err(CodecError::BufferSizeMismatch {
+                expected: expected_size,
+                actual: source.len(),
+            });
+        }
+
+        let destination_rows = self.planes.iter_mut().flat_map(|plane| plane.rows_mut());
+        let mut offset = 0;
+        for destination_row in destination_rows {
+            let row_bytes = destination_row.len();
+            copy_row(destination_row, &source[offset..offset + row_bytes]);
+            offset += row_bytes;
+        }
+
+        Ok(())
+    }
+}
+
+fn checked_stride(avframe: &AVFrame, index: usize, layout: &PlaneLayout) -> Result<usize, CodecError> {
+    let stride = avframe.linesize[index];
+    if layout.height > 0 && (stride < layout.row_bytes as i32 || avframe.data[index].is_null()) {
+        return Err(CodecError::UnsupportedPixelFormat(avframe.format));
+    }
+
+    // Rows of empty planes are never visited, any non-zero chunk size will do
+    Ok((stride as usize).max(1))
+}
+
+/// Size of a non-empty plane, checked against the bytes left in the frame buffer holding it.
+fn checked_plane_size(
+    avframe: &AVFrame,
+    index: usize,
+    stride: usize,
+    layout: &PlaneLayout,
+) -> Result<usize, CodecError> {
+    // The plane spans its stride for every row but the last one, which may be left unpadded
+    let plane_size = stride * (layout.height - 1) + layout.row_bytes;
+
+    let plane_start = avframe.data[index] as usize;
+    let available = avframe
+        .buf
+        .iter()
+        .filter_map(|buffer| unsafe { buffer.as_ref() })
+        .find_map(|buffer| {
+            let offset = plane_start.checked_sub(buffer.data as usize)?;
+            buffer
+                .size
+                .checked_sub(offset)
+                .filter(|&available| available > 0)
+        })
+        .unwrap_or(0);
+
+    if plane_size > available {
+        return Err(CodecError::BufferSizeMismatch {
+            expected: plane_size,
+            actual: available,
+        });
+    }
+
+    Ok(plane_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YUV420P: ffi::AVPixelFormat = ffi::AVPixelFormat_AV_PIX_FMT_YUV420P;
+
+    fn layout(width: usize, row_bytes: usize, height: usize) -> PlaneLayout {
+        PlaneLayout {
+            width,
+            row_bytes,
+            height,
+        }
+    }
+
+    fn allocate_frame(pixel_format: ffi::AVPixelFormat, width: i32, height: i32) -> AVFrame {
+        let mut avframe = AVFrame::new();
+        avframe.set_format(pixel_format);
+        avframe.set_width(width);
+        avframe.set_height(height);
+        avframe.alloc_buffer().unwrap();
+        avframe
+    }
+
+    #[test]
+    fn rounds_subsampled_chroma_planes_up() {
+        let layouts = plane_layouts(YUV420P, 5, 3).unwrap();
+
+        assert_eq!(layouts, [layout(5, 5, 3), layout(3, 3, 2), layout(3, 3, 2)]);
+        assert_eq!(packed_size(&layouts), 27);
+    }
+
+    #[test]
+    fn lays_out_interleaved_chroma_planes() {
+        let layouts = plane_layouts(ffi::AVPixelFormat_AV_PIX_FMT_NV12, 4, 4).unwrap();
+
+        assert_eq!(layouts, [layout(4, 4, 4), layout(2, 4, 2)]);
+    }
+
+    #[test]
+    fn lays_out_packed_pixels() {
+        let layouts = plane_layouts(ffi::AVPixelFormat_AV_PIX_FMT_RGBA, 3, 2).unwrap();
+
+        assert_eq!(layouts, [layout(3, 12, 2)]);
+    }
+
+    #[test]
+    fn rejects_unknown_pixel_formats() {
+        let pixel_format = ffi::AVPixelFormat_AV_PIX_FMT_NONE;
+
+        assert_eq!(
+            plane_layouts(pixel_format, 4, 4),
+            Err(CodecError::UnsupportedPixelFormat(pixel_format))
+        );
+        assert_eq!(
+            check_pixel_format(pixel_format, &[YUV420P]),
+            Err(CodecError::UnsupportedPixelFormat(pixel_format))
+        );
+        assert_eq!(check_pixel_format(YUV420P, &[YUV420P]), Ok(()));
+    }
+
+    #[test]
+    fn copies_packed_pictures_through_padded_planes() {
+        let mut avframe = allocate_frame(YUV420P, 5, 3);
+        let packed: Vec<u8> = (0..27).collect();
+
+        let mut planes_mut = PlanesMut::new(&mut avframe).unwrap();
+        assert_eq!(planes_mut.packed_size(), packed.len());
+        planes_mut.copy_from_packed(&packed).unwrap();
+
+        let planes = Planes::new(&avframe).unwrap();
+        assert_eq!(planes.len(), 3);
+        assert!(planes
+            .iter()
+            .all(|plane| plane.stride() >= plane.row_bytes()));
+        assert_eq!(planes.get(1).unwrap().row(1), Some(&packed[18..21]));
+
+        let mut copied = BytesMut::new();
+        planes.copy_to_packed(&mut copied);
+        assert_eq!(&copied[..], &packed[..]);
+    }
+
+    #[test]
+    fn rejects_packed_pictures_of_another_size() {
+        let mut avframe = allocate_frame(YUV420P, 5, 3);
+        let mut planes_mut = PlanesMut::new(&mut avframe).unwrap();
+
+        assert_eq!(
+            planes_mut.copy_from_packed(&[0; 26]),
+            Err(CodecError::BufferSizeMismatch {
+                expected: 27,
+                actual: 26
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_planes_beyond_the_frame_buffers() {
+        let mut avframe = allocate_frame(YUV420P, 16, 16);
+        avframe.set_height(64);
+
+        assert!(matches!(
+            Planes::new(&avframe),
+            Err(CodecError::BufferSizeMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,229 @@
+use remotia::buffers::BufMut;
+
+use crate::{
+    bitstream::av1::{read_leb128, ObuType, Obus},
+    error::CodecError,
+};
+
+use super::RtpPayloads;
+
+const CONTINUES_FROM_PREVIOUS: u8 = 0x80;
+const CONTINUES_IN_NEXT: u8 = 0x40;
+const NEW_CODED_VIDEO_SEQUENCE: u8 = 0x08;
+
+const OBU_HAS_EXTENSION: u8 = 0x04;
+const OBU_HAS_SIZE_FIELD: u8 = 0x02;
+
+const TEMPORAL_DELIMITER: [u8; 2] = [0x12, 0x00];
+
+/// Packs the OBUs of a temporal unit into aggregation packets, dropping the temporal delimiters and the size fields.
+///
+/// Every OBU element is preceded by its size, and the ones crossing a packet boundary are fragmented.
+pub(super) fn packetize(
+    temporal_unit: &[u8],
+    max_payload_size: usize,
+    payloads: &mut RtpPayloads,
+) -> Result<(), CodecError> {
+    let mut elements = Vec::with_capacity(temporal_unit.len());
+    let mut element_ends = Vec::new();
+    let mut new_coded_video_sequence = false;
+
+    for obu in Obus::new(temporal_unit) {
+        let obu = obu?;
+        match obu.obu_type() {
+            ObuType::TemporalDelimiter | ObuType::TileList | ObuType::Padding => continue,
+            ObuType::SequenceHeader => new_coded_video_sequence = true,
+            _ => {}
+        }
+
+        let header = obu.data()[0];
+        elements.push(header & !OBU_HAS_SIZE_FIELD);
+        if header & OBU_HAS_EXTENSION != 0 {
+            elements.push(obu.data()[1]);
+        }
+        elements.extend_from_slice(obu.payload());
+        element_ends.push(elements.len());
+    }
+
+    // Space left in the packet being filled, if any
+    let mut packet_space = None;
+    let mut element_start = 0;
+    for element_end in element_ends {
+        let element = &elements[element_start..element_end];
+        element_start = element_end;
+
+        let mut remaining = element;
+        while !remaining.is_empty() {
+            // Room for at least the size and one byte of the element, otherwise start a new packet
+            let space = match packet_space {
+                Some(space) if space >= 2 => space,
+                open_packet => {
+                    if open_packet.is_some() {
+                        payloads.finish_payload();
+                    }
+
+                    let mut aggregation_header = 0;
+                    if remaining.len() < element.len() {
+                        aggregation_header |= CONTINUES_FROM_PREVIOUS;
+                    }
+                    if payloads.len() == 0 && new_coded_video_sequence {
+                        aggregation_header |= NEW_CODED_VIDEO_SEQUENCE;
+                    }
+
+                    payloads.put_slice(&[aggregation_header]);
+                    max_payload_size - 1
+                }
+            };
+
+            let mut fragment_size = remaining.len().min(space - 1);
+            while leb128_size(fragment_size) + fragment_size > space {
+                fragment_size -= 1;
+            }
+
+            let mut size_field = [0; 10];
+            let size_field_length = write_leb128(fragment_size as u64, &mut size_field);
+            payloads.put_slice(&size_field[..size_field_length]);
+            payloads.put_slice(&remaining[..fragment_size]);
+            remaining = &remaining[fragment_size..];
+
+            if remaining.is_empty() {
+                packet_space = Some(space - size_field_length - fragment_size);
+            } else {
+                payloads.current_payload_mut()[0] |= CONTINUES_IN_NEXT;
+                payloads.finish_payload();
+                packet_space = None;
+            }
+        }
+    }
+
+    if packet_space.is_some() {
+        payloads.finish_payload();
+    }
+
+    Ok(())
+}
+
+pub(super) fn starts_frame(payload: &[u8]) -> bool {
+    payload
+        .first()
+        .is_some_and(|&aggregation_header| aggregation_header & CONTINUES_FROM_PREVIOUS == 0)
+}
+
+/// Rebuilds a temporal unit in the low overhead bitstream format, starting with a temporal delimiter and with size
+/// fields on every OBU.
+pub(super) fn depacketize(payloads: &[&[u8]], output: &mut Vec<u8>) -> Result<(), CodecError> {
+    output.extend_from_slice(&TEMPORAL_DELIMITER);
+
+    let mut element = Vec::new();
+    let mut fragmented = false;
+
+    for payload in payloads {
+        let Some((&aggregation_header, mut remaining)) = payload.split_first() else {
+            return Err(CodecError::MalformedBitstream("empty RTP payload"));
+        };
+
+        let continues_from_previous = aggregation_header & CONTINUES_FROM_PREVIOUS != 0;
+        let continues_in_next = aggregation_header & CONTINUES_IN_NEXT != 0;
+        let elements_count = ((aggregation_header >> 4) & 0x03) as usize;
+
+        if continues_from_previous != fragmented {
+            return Err(CodecError::MalformedBitstream("inconsistent OBU fragmentation"));
+        }
+
+        let mut index = 0;
+        while !remaining.is_empty() {
+            index += 1;
+
+            // When the elements are counted, the last one has no size field
+            let size = if index == elements_count {
+                remaining.len()
+            } else {
+                let (size, size_field_length) = read_leb128(remaining)?;
+                remaining = &remaining[size_field_length..];
+                size as usize
+            };
+
+            if size > remaining.len() {
+                return Err(CodecError::MalformedBitstream("truncated OBU element"));
+            }
+            let (fragment, rest) = remaining.split_at(size);
+            remaining = rest;
+
+            if !(index == 1 && fragmented) {
+                element.clear();
+            }
+            element.extend_from_slice(fragment);
+
+            fragmented = remaining.is_empty() && continues_in_next;
+            if !fragmented {
+                write_obu(&element, output)?;
+            }
+        }
+    }
+
+    if fragmented {
+        return Err(CodecError::MalformedBitstream("unterminated OBU element"));
+    }
+
+    Ok(())
+}
+
+/// Writes an OBU element with a size field, unless it already has one.
+fn write_obu(element: &[u8], output: &mut Vec<u8>) -> Result<(), CodecError> {
+    let Some(&header) = element.first() else {
+        return Err(CodecError::MalformedBitstream("empty OBU element"));
+    };
+
+    if ObuType::from_raw((header >> 3) & 0x0f) == ObuType::TemporalDelimiter {
+        return Ok(());
+    }
+
+    if header & OBU_HAS_SIZE_FIELD != 0 {
+        output.extend_from_slice(element);
+        return Ok(());
+    }
+
+    let header_size = if header & OBU_HAS_EXTENSION != 0 { 2 } else { 1 };
+    if element.len() < header_size {
+        return Err(CodecError::MalformedBitstream("truncated OBU header"));
+    }
+
+    output.put_u8(header | OBU_HAS_SIZE_FIELD);
+    output.extend_from_slice(&element[1..header_size]);
+
+    let payload = &element[header_size..];
+    let mut size_field = [0; 10];
+    let size_field_length = write_leb128(payload.len() as u64, &mut size_field);
+    output.extend_from_slice(&size_field[..size_field_length]);
+    output.extend_from_slice(payload);
+
+    Ok(())
+}
+
+fn leb128_size(value: usize) -> usize {
+    let mut size = 1;
+    let mut value = value >> 7;
+    while value > 0 {
+        size += 1;
+        value >>= 7;
+    }
+
+    size
+}
+
+/// Writes a value as little-endian base 128, returning the number of bytes written.
+fn write_leb128(mut value: u64, output: &mut [u8; 10]) -> usize {
+    let mut length = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            output[length] = byte;
+            return length + 1;
+        }
+
+        output[length] = byte | 0x80;
+        length += 1;
+    }
+}
@@ -0,0 +1,190 @@
+use remotia::traits::FrameProcessor;
+
+use async_trait::async_trait;
+
+use crate::{
+    error::{CodecError, CodecErrorKind, CodecErrorReport},
+    ffi,
+    timestamps::rescale,
+    EncodedPacketSink, EncodedPacketSource, PacketMetadata,
+};
+
+use super::{av1, h264, is_keyframe, vp8, vp9, FramedRtpPackets, RtpHeader, RtpPayloadFormat, RTP_CLOCK_RATE};
+
+enum DepayloadError {
+    Malformed(CodecError),
+    PacketLoss(&'static str),
+}
+
+impl From<CodecError> for DepayloadError {
+    fn from(error: CodecError) -> Self {
+        Self::Malformed(error)
+    }
+}
+
+/// Rebuilds the access unit of each frame out of the framed RTP packets in its packet buffer, as written by the
+/// `RtpPayloader`, and sets the frame id back from the RTP timestamp.
+///
+/// Packets may come in any order. When some of them are missing, the buffer is emptied and a `PacketLoss` error is
+/// reported, so that the frame does not reach the decoder.
+///
+/// Packets missing at the start of a frame are told by the start of frame flag of the VP8, VP9 and AV1 payloads. H.264
+/// payloads have no such flag, so a gap after the marker packet of the previous frame is taken as a loss too, which
+/// also drops the frame following a lost marker packet.
+pub struct RtpDepayloader {
+    pub(super) payload_format: RtpPayloadFormat,
+    pub(super) time_base: ffi::AVRational,
+    pub(super) highest_timestamp: Option<i64>,
+    pub(super) last_marker_sequence_number: Option<u16>,
+    pub(super) access_unit: Vec<u8>,
+}
+
+impl RtpDepayloader {
+    fn depayload<F>(&mut self, frame_data: &mut F) -> Result<(), DepayloadError>
+    where
+        F: EncodedPacketSink + EncodedPacketSource,
+    {
+        let buffer = frame_data
+            .get_packet_data_buffer()
+            .ok_or(CodecError::MissingBuffer)?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        let mut packets = Vec::new();
+        for packet in FramedRtpPackets::new(buffer) {
+            packets.push(RtpHeader::parse(packet?)?);
+        }
+
+        // Sort around the first packet, so that the order survives the wrap around of sequence numbers
+        let reference = packets[0].0.sequence_number.wrapping_sub(u16::MAX / 2);
+        packets.sort_by_key(|(header, _)| header.sequence_number.wrapping_sub(reference));
+        packets.dedup_by_key(|(header, _)| header.sequence_number);
+
+        let (first_header, first_payload) = packets[0];
+        let (last_header, _) = packets[packets.len() - 1];
+
+        if packets
+            .iter()
+            .any(|(header, _)| header.timestamp != first_header.timestamp)
+        {
+            return Err(CodecError::MalformedBitstream("RTP packets of different frames").into());
+        }
+
+        let follows_previous_frame = self.follows_previous_frame(first_header.sequence_number);
+        if last_header.marker {
+            self.record_marker(last_header.sequence_number);
+        }
+
+        if packets
+            .windows(2)
+            .any(|pair| pair[1].0.sequence_number != pair[0].0.sequence_number.wrapping_add(1))
+        {
+            return Err(DepayloadError::PacketLoss("missing packets within the frame"));
+        }
+        if !last_header.marker {
+            return Err(DepayloadError::PacketLoss("missing packets at the end of the frame"));
+        }
+
+        let starts_frame = match self.payload_format {
+            // Any NAL unit may open an access unit, hence only a gap after the previous marker packet tells a lost
+            // leading packet
+            RtpPayloadFormat::H264 => h264::starts_frame(first_payload) && follows_previous_frame != Some(false),
+            RtpPayloadFormat::Vp8 => vp8::starts_frame(first_payload),
+            RtpPayloadFormat::Vp9 => vp9::starts_frame(first_payload),
+            RtpPayloadFormat::Av1 => av1::starts_frame(first_payload),
+        };
+        if !starts_frame {
+            return Err(DepayloadError::PacketLoss("missing packets at the start of the frame"));
+        }
+
+        let payloads: Vec<&[u8]> = packets.iter().map(|(_, payload)| *payload).collect();
+        self.access_unit.clear();
+        match self.payload_format {
+            RtpPayloadFormat::H264 => h264::depacketize(&payloads, &mut self.access_unit)?,
+            RtpPayloadFormat::Vp8 => vp8::depacketize(&payloads, &mut self.access_unit)?,
+            RtpPayloadFormat::Vp9 => vp9::depacketize(&payloads, &mut self.access_unit)?,
+            RtpPayloadFormat::Av1 => av1::depacketize(&payloads, &mut self.access_unit)?,
+        }
+
+        let timestamp = self.extend_timestamp(first_header.timestamp);
+        let frame_id = rescale(timestamp, RTP_CLOCK_RATE, self.time_base);
+
+        frame_data.clear_packet_data();
+        frame_data.clear_packet_metadata();
+        frame_data.write_packet_data(&self.access_unit)?;
+        frame_data.set_frame_id(frame_id);
+        frame_data.write_packet_metadata(PacketMetadata {
+            keyframe: is_keyframe(self.payload_format, &self.access_unit),
+            pts: frame_id,
+            dts: frame_id,
+            duration: 0,
+            offset: 0,
+            size: self.access_unit.len(),
+        });
+
+        Ok(())
+    }
+
+    /// Tells whether a frame starting at `sequence_number` directly follows the last frame received, or `None` when
+    /// there is no such frame or when this one is older.
+    fn follows_previous_frame(&self, sequence_number: u16) -> Option<bool> {
+        let expected = self.last_marker_sequence_number?.wrapping_add(1);
+        match sequence_number.wrapping_sub(expected) as i16 {
+            0 => Some(true),
+            distance if distance > 0 => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Records the marker packet of a frame, unless a newer frame has already been received.
+    fn record_marker(&mut self, sequence_number: u16) {
+        match self.last_marker_sequence_number {
+            Some(last) if sequence_number.wrapping_sub(last) as i16 <= 0 => {}
+            _ => self.last_marker_sequence_number = Some(sequence_number),
+        }
+    }
+
+    /// Extends a 32 bits RTP timestamp across wrap arounds, relative to the highest timestamp received so far.
+    fn extend_timestamp(&mut self, timestamp: u32) -> i64 {
+        let extended = match self.highest_timestamp {
+            Some(highest) => highest + timestamp.wrapping_sub(highest as u32) as i32 as i64,
+            None => timestamp as i64,
+        };
+
+        self.highest_timestamp = Some(self.highest_timestamp.unwrap_or(extended).max(extended));
+        extended
+    }
+}
+
+#[async_trait]
+impl<F> FrameProcessor<F> for RtpDepayloader
+where
+    F: EncodedPacketSink + EncodedPacketSource + Send + 'static,
+{
+    async fn process(&mut self, mut frame_data: F) -> Option<F> {
+        let codec_name = self.payload_format.name();
+        let report = match self.depayload(&mut frame_data) {
+            Ok(()) => None,
+            Err(DepayloadError::Malformed(error)) => {
+                log::warn!("Unable to depayload frame {}: {}", frame_data.get_frame_id(), error);
+                Some(match error {
+                    CodecError::MissingBuffer => CodecErrorReport::missing_packet_buffer(codec_name),
+                    error => CodecErrorReport::from_codec_error(CodecErrorKind::Payload, &error, codec_name),
+                })
+            }
+            Err(DepayloadError::PacketLoss(reason)) => {
+                log::debug!("Packet loss in frame {}: {}", frame_data.get_frame_id(), reason);
+                Some(CodecErrorReport::new(CodecErrorKind::PacketLoss, None, codec_name))
+            }
+        };
+
+        if let Some(report) = report {
+            frame_data.clear_packet_data();
+            frame_data.clear_packet_metadata();
+            frame_data.report_codec_error(report);
+        }
+
+        Some(frame_data)
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{
+    bitstream::h26x::{AnnexBNalUnits, NalCodec},
+    error::CodecError,
+};
+
+use super::RtpPayloads;
+
+const STAP_A: u8 = 24;
+const FU_A: u8 = 28;
+
+const FU_START: u8 = 0x80;
+const FU_END: u8 = 0x40;
+
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// Sends NAL units that fit in a packet on their own, or aggregated with the following ones in a STAP-A, and
+/// fragments the larger ones in FU-As.
+pub(super) fn packetize(
+    access_unit: &[u8],
+    max_payload_size: usize,
+    payloads: &mut RtpPayloads,
+) -> Result<(), CodecError> {
+    let nal_units: Vec<&[u8]> = AnnexBNalUnits::new(NalCodec::H264, access_unit)
+        .map(|nal_unit| nal_unit.data())
+        .collect();
+    if nal_units.is_empty() {
+        return Err(CodecError::MalformedBitstream("no NAL unit in the access unit"));
+    }
+
+    let mut index = 0;
+    while index < nal_units.len() {
+        let nal_unit = nal_units[index];
+        if nal_unit.len() > max_payload_size {
+            fragment(nal_unit, max_payload_size, payloads);
+            index += 1;
+            continue;
+        }
+
+        let mut aggregated_size = 1;
+        let mut end = index;
+        while let Some(next_unit) = nal_units.get(end) {
+            if aggregated_size + 2 + next_unit.len() > max_payload_size {
+                break;
+            }
+            aggregated_size += 2 + next_unit.len();
+            end += 1;
+        }
+
+        if end - index < 2 {
+            payloads.put_slice(nal_unit);
+        } else {
+            let aggregated_units = &nal_units[index..end];
+
+            // The forbidden bit and the highest importance of the aggregated units
+            let forbidden = aggregated_units
+                .iter()
+                .fold(0, |bit, unit| bit | (unit[0] & 0x80));
+            let nri = aggregated_units
+                .iter()
+                .map(|unit| unit[0] & 0x60)
+                .max()
+                .unwrap_or(0);
+            payloads.put_slice(&[forbidden | nri | STAP_A]);
+
+            for unit in aggregated_units {
+                payloads.put_slice(&(unit.len() as u16).to_be_bytes());
+                payloads.put_slice(unit);
+            }
+        }
+
+        payloads.finish_payload();
+        index = end.max(index + 1);
+    }
+
+    Ok(())
+}
+
+fn fragment(nal_unit: &[u8], max_payload_size: usize, payloads: &mut RtpPayloads) {
+    let nal_header = nal_unit[0];
+    let indicator = (nal_header & 0xe0) | FU_A;
+
+    let chunks = nal_unit[1..].chunks(max_payload_size - 2);
+    let chunks_count = chunks.len();
+    for (index, chunk) in chunks.enumerate() {
+        let mut fu_header = nal_header & 0x1f;
+        if index == 0 {
+            fu_header |= FU_START;
+        }
+        if index + 1 == chunks_count {
+            fu_header |= FU_END;
+        }
+
+        payloads.put_slice(&[indicator, fu_header]);
+        payloads.put_slice(chunk);
+        payloads.finish_payload();
+    }
+}
+
+pub(super) fn starts_frame(payload: &[u8]) -> bool {
+    match payload {
+        [indicator, fu_header, ..] if indicator & 0x1f == FU_A => fu_header & FU_START != 0,
+        [indicator, ..] => indicator & 0x1f != FU_A,
+        [] => false,
+    }
+}
+
+/// Writes the NAL units of the payloads in Annex B.
+pub(super) fn depacketize(payloads: &[&[u8]], output: &mut Vec<u8>) -> Result<(), CodecError> {
+    let mut fragmented = false;
+
+    for payload in payloads {
+        let Some((&nal_header, body)) = payload.split_first() else {
+            return Err(CodecError::MalformedBitstream("empty RTP payload"));
+        };
+
+        let packet_type = nal_header & 0x1f;
+        if fragmented && packet_type != FU_A {
+            return Err(CodecError::MalformedBitstream("unterminated FU-A"));
+        }
+
+        match packet_type {
+            1..=23 => {
+                output.extend_from_slice(&START_CODE);
+                output.extend_from_slice(payload);
+            }
+            STAP_A => {
+                let mut remaining = body;
+                while !remaining.is_empty() {
+                    let unit = match remaining {
+                        [high, low, rest @ ..] => rest.get(..u16::from_be_bytes([*high, *low]) as usize),
+                        _ => None,
+                    }
+                    .ok_or(CodecError::MalformedBitstream("truncated STAP-A"))?;
+
+                    output.extend_from_slice(&START_CODE);
+                    output.extend_from_slice(unit);
+                    remaining = &remaining[2 + unit.len()..];
+                }
+            }
+            FU_A => {
+                let Some((&fu_header, fragment)) = body.split_first() else {
+                    return Err(CodecError::MalformedBitstream("truncated FU-A"));
+                };
+
+                if fu_header & FU_START != 0 {
+                    if fragmented {
+                        return Err(CodecError::MalformedBitstream("unterminated FU-A"));
+                    }
+                    output.extend_from_slice(&START_CODE);
+                    output.push((nal_header & 0xe0) | (fu_header & 0x1f));
+                    fragmented = true;
+                } else if !fragmented {
+                    return Err(CodecError::MalformedBitstream("FU-A without start fragment"));
+                }
+
+                output.extend_from_slice(fragment);
+                if fu_header & FU_END != 0 {
+                    fragmented = false;
+                }
+            }
+            _ => return Err(CodecError::MalformedBitstream("unsupported H.264 packetization")),
+        }
+    }
+
+    if fragmented {
+        return Err(CodecError::MalformedBitstream("unterminated FU-A"));
+    }
+
+    Ok(())
+}
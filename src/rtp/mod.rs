@@ -0,0 +1,572 @@
+//! RTP packetization of encoded frames, following the payload format of each codec.
+//!
+//! The packet buffer of a payloaded frame holds all of its RTP packets framed as in RFC 4571, each one preceded by
+//! its size as a big-endian 16 bits integer. The depayloader expects the same layout, hence receivers only need to
+//! gather the datagrams sharing an RTP timestamp into the buffer of a frame.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+use crate::{bitstream, builder::unwrap_mandatory, error::CodecError, ffi, timestamps::DEFAULT_TIME_BASE};
+
+mod av1;
+mod h264;
+mod vp8;
+mod vp9;
+
+mod depayloader;
+mod packet;
+mod payloader;
+
+pub use depayloader::*;
+pub use packet::*;
+pub use payloader::*;
+
+const RTP_CLOCK_RATE: ffi::AVRational = ffi::AVRational { num: 1, den: 90 * 1000 };
+
+const DEFAULT_MTU: usize = 1200;
+const DEFAULT_PAYLOAD_TYPE: u8 = 96;
+
+/// Smallest payload which still fits the descriptors of every payload format along with some data.
+const MIN_PAYLOAD_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtpPayloadFormat {
+    /// RFC 6184 in non-interleaved mode, with single NAL unit, STAP-A and FU-A packets. Access units are in Annex B
+    H264,
+    /// RFC 7741
+    Vp8,
+    /// RFC 9628, in non-flexible mode and without scalability structure
+    Vp9,
+    /// AV1 RTP payload format, with temporal units in the low overhead bitstream format
+    Av1,
+}
+
+impl RtpPayloadFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::H264 => "h264",
+            Self::Vp8 => "vp8",
+            Self::Vp9 => "vp9",
+            Self::Av1 => "av1",
+        }
+    }
+
+    pub fn codec_id(&self) -> ffi::AVCodecID {
+        match self {
+            Self::H264 => ffi::AVCodecID_AV_CODEC_ID_H264,
+            Self::Vp8 => ffi::AVCodecID_AV_CODEC_ID_VP8,
+            Self::Vp9 => ffi::AVCodecID_AV_CODEC_ID_VP9,
+            Self::Av1 => ffi::AVCodecID_AV_CODEC_ID_AV1,
+        }
+    }
+}
+
+pub struct RtpPayloaderBuilder {
+    payload_format: Option<RtpPayloadFormat>,
+    mtu: Option<usize>,
+    payload_type: Option<u8>,
+    ssrc: Option<u32>,
+    initial_sequence_number: Option<u16>,
+    time_base: Option<ffi::AVRational>,
+}
+
+impl Default for RtpPayloaderBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RtpPayloaderBuilder {
+    pub fn new() -> Self {
+        Self {
+            payload_format: None,
+            mtu: None,
+            payload_type: None,
+            ssrc: None,
+            initial_sequence_number: None,
+            time_base: None,
+        }
+    }
+
+    builder_set!(payload_format, RtpPayloadFormat);
+
+    // Maximum size of each RTP packet, header included but framing excluded. Defaults to 1200 bytes, which fits in
+    // the usual path MTU once the IP and UDP headers are added
+    builder_set!(mtu, usize);
+    builder_set!(payload_type, u8);
+
+    // Both default to random values, as recommended by RFC 3550
+    builder_set!(ssrc, u32);
+    builder_set!(initial_sequence_number, u16);
+
+    // Time base of the frame ids, which are rescaled to the 90 kHz clock of the RTP timestamps
+    builder_set!(time_base, ffi::AVRational);
+
+    pub fn build(self) -> Result<RtpPayloader, CodecError> {
+        let payload_format = unwrap_mandatory(self.payload_format, "payload_format")?;
+
+        let mtu = self.mtu.unwrap_or(DEFAULT_MTU);
+        if mtu < RTP_HEADER_SIZE + MIN_PAYLOAD_SIZE || mtu > u16::MAX as usize {
+            return Err(CodecError::MtuTooSmall(mtu));
+        }
+
+        Ok(RtpPayloader {
+            payload_format,
+            max_payload_size: mtu - RTP_HEADER_SIZE,
+            payload_type: self.payload_type.unwrap_or(DEFAULT_PAYLOAD_TYPE) & 0x7f,
+            ssrc: self.ssrc.unwrap_or_else(|| random_u64() as u32),
+            sequence_number: self
+                .initial_sequence_number
+                .unwrap_or_else(|| random_u64() as u16),
+            time_base: self.time_base.unwrap_or(DEFAULT_TIME_BASE),
+            payloads: RtpPayloads::default(),
+            packet: Vec::with_capacity(mtu + 2),
+        })
+    }
+}
+
+pub struct RtpDepayloaderBuilder {
+    payload_format: Option<RtpPayloadFormat>,
+    time_base: Option<ffi::AVRational>,
+}
+
+impl Default for RtpDepayloaderBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RtpDepayloaderBuilder {
+    pub fn new() -> Self {
+        Self {
+            payload_format: None,
+            time_base: None,
+        }
+    }
+
+    builder_set!(payload_format, RtpPayloadFormat);
+
+    // Time base in which the RTP timestamps are converted back to frame ids
+    builder_set!(time_base, ffi::AVRational);
+
+    pub fn build(self) -> Result<RtpDepayloader, CodecError> {
+        Ok(RtpDepayloader {
+            payload_format: unwrap_mandatory(self.payload_format, "payload_format")?,
+            time_base: self.time_base.unwrap_or(DEFAULT_TIME_BASE),
+            highest_timestamp: None,
+            last_marker_sequence_number: None,
+            access_unit: Vec::new(),
+        })
+    }
+}
+
+/// Payloads of the RTP packets of a frame, stored back to back.
+#[derive(Default)]
+struct RtpPayloads {
+    data: Vec<u8>,
+    ends: Vec<usize>,
+}
+
+impl RtpPayloads {
+    fn clear(&mut self) {
+        self.data.clear();
+        self.ends.clear();
+    }
+
+    fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    fn put_slice(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Bytes written since the last finished payload.
+    fn current_payload_mut(&mut self) -> &mut [u8] {
+        let start = self.ends.last().copied().unwrap_or(0);
+        &mut self.data[start..]
+    }
+
+    fn finish_payload(&mut self) {
+        self.ends.push(self.data.len());
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        let starts = std::iter::once(0).chain(self.ends.iter().copied());
+        starts
+            .zip(self.ends.iter().copied())
+            .map(|(start, end)| &self.data[start..end])
+    }
+}
+
+/// Tells whether an access unit can be decoded on its own.
+fn is_keyframe(payload_format: RtpPayloadFormat, access_unit: &[u8]) -> bool {
+    bitstream::is_keyframe(payload_format.codec_id(), access_unit).unwrap_or(false)
+}
+
+fn random_u64() -> u64 {
+    // Every RandomState is seeded differently, which is enough for identifiers that only need to differ
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use remotia::traits::FrameProcessor;
+
+    use crate::{
+        bitstream::h26x::{AnnexBNalUnits, NalCodec},
+        error::{CodecErrorKind, CodecErrorReport},
+        CodecFrame, EncodedPacketSink, EncodedPacketSource, PacketMetadata,
+    };
+
+    use super::*;
+
+    const FRAME_ID: i64 = 1234;
+
+    #[derive(Default)]
+    struct TestFrame {
+        frame_id: i64,
+        packet_buffer: Vec<u8>,
+        packets: Vec<PacketMetadata>,
+        errors: Vec<CodecErrorKind>,
+    }
+
+    impl CodecFrame for TestFrame {
+        fn set_frame_id(&mut self, frame_id: i64) {
+            self.frame_id = frame_id;
+        }
+
+        fn get_frame_id(&self) -> i64 {
+            self.frame_id
+        }
+
+        fn report_codec_error(&mut self, report: CodecErrorReport) {
+            self.errors.push(report.kind);
+        }
+    }
+
+    impl EncodedPacketSink for TestFrame {
+        fn write_packet_data(&mut self, packet_data: &[u8]) -> Result<(), CodecError> {
+            self.packet_buffer.extend_from_slice(packet_data);
+            Ok(())
+        }
+
+        fn report_flush_error(&mut self) {}
+
+        fn clear_packet_data(&mut self) {
+            self.packet_buffer.clear();
+        }
+
+        fn write_packet_metadata(&mut self, metadata: PacketMetadata) {
+            self.packets.push(metadata);
+        }
+
+        fn clear_packet_metadata(&mut self) {
+            self.packets.clear();
+        }
+    }
+
+    impl EncodedPacketSource for TestFrame {
+        fn get_packet_data_buffer(&self) -> Option<&[u8]> {
+            Some(&self.packet_buffer)
+        }
+    }
+
+    fn payloader(payload_format: RtpPayloadFormat, mtu: usize) -> RtpPayloader {
+        RtpPayloaderBuilder::new()
+            .payload_format(payload_format)
+            .mtu(mtu)
+            .time_base(RTP_CLOCK_RATE)
+            .build()
+            .unwrap()
+    }
+
+    fn depayloader(payload_format: RtpPayloadFormat) -> RtpDepayloader {
+        RtpDepayloaderBuilder::new()
+            .payload_format(payload_format)
+            .time_base(RTP_CLOCK_RATE)
+            .build()
+            .unwrap()
+    }
+
+    /// Payloads an access unit, returning its framed RTP packets one by one.
+    async fn payload(payloader: &mut RtpPayloader, frame_id: i64, access_unit: &[u8]) -> Vec<Vec<u8>> {
+        let frame_data = TestFrame {
+            frame_id,
+            packet_buffer: access_unit.to_vec(),
+            ..Default::default()
+        };
+        let frame_data = payloader.process(frame_data).await.unwrap();
+        assert!(frame_data.errors.is_empty());
+
+        frame_data
+            .packets
+            .iter()
+            .map(|packet| {
+                assert!(packet.size <= payloader.max_payload_size + RTP_HEADER_SIZE);
+                frame_data.packet_buffer[packet.offset - 2..packet.offset + packet.size].to_vec()
+            })
+            .collect()
+    }
+
+    async fn depayload(depayloader: &mut RtpDepayloader, packets: &[Vec<u8>]) -> TestFrame {
+        let frame_data = TestFrame {
+            packet_buffer: packets.concat(),
+            ..Default::default()
+        };
+        depayloader.process(frame_data).await.unwrap()
+    }
+
+    /// Payloads and depayloads an access unit, with its packets in reverse order.
+    async fn round_trip(payload_format: RtpPayloadFormat, mtu: usize, access_unit: &[u8]) -> TestFrame {
+        let mut packets = payload(&mut payloader(payload_format, mtu), FRAME_ID, access_unit).await;
+        assert!(packets.len() > 1);
+        packets.reverse();
+
+        let frame_data = depayload(&mut depayloader(payload_format), &packets).await;
+        assert!(frame_data.errors.is_empty());
+        assert_eq!(frame_data.frame_id, FRAME_ID);
+        frame_data
+    }
+
+    fn data(size: usize) -> impl Iterator<Item = u8> {
+        // Never zero, so that no start code shows up in the NAL units
+        (0..size).map(|index| (index % 251) as u8 + 1)
+    }
+
+    fn nal_units(access_unit: &[u8]) -> Vec<&[u8]> {
+        AnnexBNalUnits::new(NalCodec::H264, access_unit)
+            .map(|nal_unit| nal_unit.data())
+            .collect()
+    }
+
+    /// Annex B access unit made of an SPS, a PPS, an IDR slice and a non IDR slice.
+    fn h264_access_unit(idr_slice_size: usize) -> Vec<u8> {
+        let mut access_unit = vec![0, 0, 0, 1, 0x67, 0x42, 0xc0, 0x1e, 0, 0, 0, 1, 0x68, 0xce, 0x3c, 0x80];
+        access_unit.extend([0, 0, 1, 0x65]);
+        access_unit.extend(data(idr_slice_size));
+        access_unit.extend([0, 0, 0, 1, 0x41]);
+        access_unit.extend(data(10));
+        access_unit
+    }
+
+    /// Annex B access unit made of non IDR slices, each one fitting in a single NAL unit packet at an MTU of 200.
+    fn h264_inter_access_unit() -> Vec<u8> {
+        let mut access_unit = Vec::new();
+        for _ in 0..3 {
+            access_unit.extend([0, 0, 0, 1, 0x41]);
+            access_unit.extend(data(150));
+        }
+        access_unit
+    }
+
+    fn vp8_keyframe() -> Vec<u8> {
+        let mut frame = vec![0x50, 0x42, 0x00, 0x9d, 0x01, 0x2a, 0x80, 0x02, 0xe0, 0x01];
+        frame.extend(data(3000));
+        frame
+    }
+
+    fn vp9_keyframe() -> Vec<u8> {
+        let mut frame = vec![0x82, 0x49, 0x83, 0x42, 0x20, 0x27, 0xf0, 0x1d, 0xf0];
+        frame.extend(data(2000));
+        frame
+    }
+
+    /// Temporal unit with a sequence header and a frame OBU larger than the MTU.
+    fn av1_temporal_unit() -> Vec<u8> {
+        let mut temporal_unit = vec![
+            0x12, 0x00, 0x0a, 0x0b, 0x00, 0x00, 0x00, 0x42, 0xab, 0xbf, 0xc3, 0x73, 0xff, 0xe6, 0x01,
+        ];
+        // Frame OBU of 4000 bytes, starting with the header of a shown keyframe
+        temporal_unit.extend([0x32, 0xa0, 0x1f, 0x10]);
+        temporal_unit.extend(data(3999));
+        temporal_unit
+    }
+
+    #[tokio::test]
+    async fn round_trips_h264_access_units() {
+        // SPS and PPS aggregated in a STAP-A packet, IDR slice fragmented in FU-A packets
+        let access_unit = h264_access_unit(5000);
+        let frame_data = round_trip(RtpPayloadFormat::H264, 1200, &access_unit).await;
+
+        assert_eq!(nal_units(&frame_data.packet_buffer), nal_units(&access_unit));
+        assert!(frame_data.packets[0].keyframe);
+
+        let frame_data = round_trip(RtpPayloadFormat::H264, 200, &h264_inter_access_unit()).await;
+        assert_eq!(
+            nal_units(&frame_data.packet_buffer),
+            nal_units(&h264_inter_access_unit())
+        );
+        assert!(!frame_data.packets[0].keyframe);
+    }
+
+    #[tokio::test]
+    async fn round_trips_vp8_frames() {
+        let frame = vp8_keyframe();
+        let frame_data = round_trip(RtpPayloadFormat::Vp8, 500, &frame).await;
+
+        assert_eq!(frame_data.packet_buffer, frame);
+        assert!(frame_data.packets[0].keyframe);
+    }
+
+    #[tokio::test]
+    async fn round_trips_vp9_frames_and_superframes() {
+        let keyframe = vp9_keyframe();
+        let frame_data = round_trip(RtpPayloadFormat::Vp9, 300, &keyframe).await;
+
+        assert_eq!(frame_data.packet_buffer, keyframe);
+        assert!(frame_data.packets[0].keyframe);
+
+        let inter_frame = [0x86, 0x00, 0x01, 0x02, 0x03];
+        let mut superframe = [&keyframe[..], &inter_frame].concat();
+        superframe.push(0xc9);
+        superframe.extend((keyframe.len() as u16).to_le_bytes());
+        superframe.extend((inter_frame.len() as u16).to_le_bytes());
+        superframe.push(0xc9);
+
+        let frame_data = round_trip(RtpPayloadFormat::Vp9, 300, &superframe).await;
+        assert_eq!(frame_data.packet_buffer, superframe);
+    }
+
+    #[tokio::test]
+    async fn round_trips_fragmented_av1_obus() {
+        let temporal_unit = av1_temporal_unit();
+
+        for mtu in [40, 100, 1200] {
+            let frame_data = round_trip(RtpPayloadFormat::Av1, mtu, &temporal_unit).await;
+
+            assert_eq!(frame_data.packet_buffer, temporal_unit);
+            assert!(frame_data.packets[0].keyframe);
+        }
+    }
+
+    #[tokio::test]
+    async fn wraps_sequence_numbers_and_timestamps() {
+        let mut payloader = RtpPayloaderBuilder::new()
+            .payload_format(RtpPayloadFormat::Vp8)
+            .mtu(500)
+            .initial_sequence_number(u16::MAX - 2)
+            .time_base(RTP_CLOCK_RATE)
+            .build()
+            .unwrap();
+        let mut depayloader = depayloader(RtpPayloadFormat::Vp8);
+
+        let frame_ids = [u32::MAX as i64 - 3000, u32::MAX as i64 + 3000];
+        for frame_id in frame_ids {
+            let packets = payload(&mut payloader, frame_id, &vp8_keyframe()).await;
+            let frame_data = depayload(&mut depayloader, &packets).await;
+
+            assert!(frame_data.errors.is_empty());
+            assert_eq!(frame_data.frame_id, frame_id);
+            assert_eq!(frame_data.packet_buffer, vp8_keyframe());
+        }
+    }
+
+    #[tokio::test]
+    async fn detects_lost_packets() {
+        let access_unit = h264_access_unit(5000);
+        let mut payloader = payloader(RtpPayloadFormat::H264, 1200);
+        let mut depayloader = depayloader(RtpPayloadFormat::H264);
+
+        // Packet in the middle of the IDR slice, then the marker packet
+        for lost_packet in [2, 6] {
+            let mut packets = payload(&mut payloader, FRAME_ID, &access_unit).await;
+            assert_eq!(packets.len(), 7);
+            packets.remove(lost_packet);
+
+            let frame_data = depayload(&mut depayloader, &packets).await;
+            assert_eq!(frame_data.errors, [CodecErrorKind::PacketLoss]);
+            assert!(frame_data.packet_buffer.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn detects_lost_packets_at_the_start_of_a_frame() {
+        let mut payloader = payloader(RtpPayloadFormat::H264, 200);
+        let mut depayloader = depayloader(RtpPayloadFormat::H264);
+
+        let packets = payload(&mut payloader, FRAME_ID, &h264_inter_access_unit()).await;
+        assert_eq!(packets.len(), 3);
+        let frame_data = depayload(&mut depayloader, &packets).await;
+        assert!(frame_data.errors.is_empty());
+
+        // The remaining packets hold whole NAL units, so only the gap after the previous marker tells the loss
+        let packets = payload(&mut payloader, FRAME_ID + 3000, &h264_inter_access_unit()).await;
+        let frame_data = depayload(&mut depayloader, &packets[1..]).await;
+        assert_eq!(frame_data.errors, [CodecErrorKind::PacketLoss]);
+
+        let packets = payload(&mut payloader, FRAME_ID + 6000, &h264_inter_access_unit()).await;
+        let frame_data = depayload(&mut depayloader, &packets).await;
+        assert!(frame_data.errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn keeps_complete_frames_after_lost_marker_packets() {
+        let formats = [
+            (RtpPayloadFormat::Vp8, vp8_keyframe()),
+            (RtpPayloadFormat::Vp9, vp9_keyframe()),
+            (RtpPayloadFormat::Av1, av1_temporal_unit()),
+        ];
+
+        for (payload_format, access_unit) in formats {
+            let mut payloader = payloader(payload_format, 500);
+            let mut depayloader = depayloader(payload_format);
+
+            let mut packets = payload(&mut payloader, FRAME_ID, &access_unit).await;
+            packets.pop();
+            let frame_data = depayload(&mut depayloader, &packets).await;
+            assert_eq!(frame_data.errors, [CodecErrorKind::PacketLoss]);
+
+            let packets = payload(&mut payloader, FRAME_ID + 3000, &access_unit).await;
+            let frame_data = depayload(&mut depayloader, &packets).await;
+            assert!(frame_data.errors.is_empty());
+            assert_eq!(frame_data.packet_buffer, access_unit);
+
+            // A whole frame lost
+            payload(&mut payloader, FRAME_ID + 6000, &access_unit).await;
+
+            let packets = payload(&mut payloader, FRAME_ID + 9000, &access_unit).await;
+            let frame_data = depayload(&mut depayloader, &packets).await;
+            assert!(frame_data.errors.is_empty());
+
+            let packets = payload(&mut payloader, FRAME_ID + 12000, &access_unit).await;
+            let frame_data = depayload(&mut depayloader, &packets[1..]).await;
+            assert_eq!(frame_data.errors, [CodecErrorKind::PacketLoss]);
+        }
+    }
+
+    #[tokio::test]
+    async fn replaces_packet_metadata() {
+        let access_unit = vp8_keyframe();
+        let encoded_packet = PacketMetadata {
+            keyframe: true,
+            pts: FRAME_ID,
+            dts: FRAME_ID,
+            duration: 0,
+            offset: 0,
+            size: access_unit.len(),
+        };
+
+        let frame_data = TestFrame {
+            frame_id: FRAME_ID,
+            packet_buffer: access_unit.clone(),
+            packets: vec![encoded_packet],
+            ..Default::default()
+        };
+        let mut payloader = payloader(RtpPayloadFormat::Vp8, 500);
+        let frame_data = payloader.process(frame_data).await.unwrap();
+
+        assert!(frame_data.packets.len() > 1);
+        for packet in &frame_data.packets {
+            let packet = &frame_data.packet_buffer[packet.offset..packet.offset + packet.size];
+            assert!(RtpHeader::parse(packet).is_ok());
+        }
+
+        let mut depayloader = depayloader(RtpPayloadFormat::Vp8);
+        let frame_data = depayloader.process(frame_data).await.unwrap();
+        assert_eq!(frame_data.packets, [encoded_packet]);
+    }
+}
@@ -0,0 +1,112 @@
+use remotia::buffers::BufMut;
+
+use crate::error::CodecError;
+
+pub const RTP_HEADER_SIZE: usize = 12;
+
+const RTP_VERSION: u8 = 2;
+
+/// Fixed header of an RTP packet, without contributing sources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtpHeader {
+    /// Set on the last packet of a frame
+    pub marker: bool,
+    pub payload_type: u8,
+    pub sequence_number: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+}
+
+impl RtpHeader {
+    /// Parses the header of an RTP packet, returning it along with the payload, stripped of the contributing sources,
+    /// header extension and padding.
+    pub fn parse(packet: &[u8]) -> Result<(Self, &[u8]), CodecError> {
+        if packet.len() < RTP_HEADER_SIZE {
+            return Err(CodecError::MalformedBitstream("truncated RTP header"));
+        }
+        if packet[0] >> 6 != RTP_VERSION {
+            return Err(CodecError::MalformedBitstream("unsupported RTP version"));
+        }
+
+        let has_padding = packet[0] & 0x20 != 0;
+        let has_extension = packet[0] & 0x10 != 0;
+        let csrc_count = (packet[0] & 0x0f) as usize;
+
+        let header = Self {
+            marker: packet[1] & 0x80 != 0,
+            payload_type: packet[1] & 0x7f,
+            sequence_number: u16::from_be_bytes([packet[2], packet[3]]),
+            timestamp: u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+            ssrc: u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]),
+        };
+
+        let mut payload_start = RTP_HEADER_SIZE + 4 * csrc_count;
+        if has_extension {
+            let extension_header = packet
+                .get(payload_start..payload_start + 4)
+                .ok_or(CodecError::MalformedBitstream("truncated RTP header extension"))?;
+            let extension_words = u16::from_be_bytes([extension_header[2], extension_header[3]]) as usize;
+            payload_start += 4 + 4 * extension_words;
+        }
+
+        let mut payload_end = packet.len();
+        if has_padding {
+            let padding_size = packet[packet.len() - 1] as usize;
+            payload_end = payload_end.saturating_sub(padding_size);
+        }
+
+        if payload_start > payload_end {
+            return Err(CodecError::MalformedBitstream("truncated RTP packet"));
+        }
+
+        Ok((header, &packet[payload_start..payload_end]))
+    }
+
+    pub fn write(&self, output: &mut impl BufMut) {
+        output.put_u8(RTP_VERSION << 6);
+        output.put_u8(((self.marker as u8) << 7) | (self.payload_type & 0x7f));
+        output.put_u16(self.sequence_number);
+        output.put_u32(self.timestamp);
+        output.put_u32(self.ssrc);
+    }
+}
+
+/// Iterates over RTP packets framed as in RFC 4571, where each packet follows its big-endian 16 bits size.
+pub struct FramedRtpPackets<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> FramedRtpPackets<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { remaining: data }
+    }
+}
+
+impl<'a> Iterator for FramedRtpPackets<'a> {
+    type Item = Result<&'a [u8], CodecError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining.is_empty() {
+            return None;
+        }
+
+        let packet = match self.remaining {
+            [high, low, rest @ ..] => {
+                let size = u16::from_be_bytes([*high, *low]) as usize;
+                rest.get(..size).map(|packet| (packet, &rest[size..]))
+            }
+            _ => None,
+        };
+
+        match packet {
+            Some((packet, rest)) => {
+                self.remaining = rest;
+                Some(Ok(packet))
+            }
+            None => {
+                self.remaining = &[];
+                Some(Err(CodecError::MalformedBitstream("truncated RTP packet framing")))
+            }
+        }
+    }
+}
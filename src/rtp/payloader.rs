@@ -0,0 +1,115 @@
+use remotia::{buffers::BufMut, traits::FrameProcessor};
+
+use async_trait::async_trait;
+
+use crate::{
+    error::{CodecError, CodecErrorKind, CodecErrorReport},
+    ffi,
+    timestamps::rescale,
+    EncodedPacketSink, EncodedPacketSource, PacketMetadata,
+};
+
+use super::{av1, h264, is_keyframe, vp8, vp9, RtpHeader, RtpPayloadFormat, RtpPayloads, RTP_CLOCK_RATE};
+
+/// Splits the access unit in the packet buffer of each frame into RTP packets no larger than the MTU, replacing
+/// the buffer with the framed packets.
+///
+/// The RTP timestamp is the frame id rescaled to the 90 kHz clock, and the marker bit is set on the last packet of
+/// each frame. Every packet is also described by a `PacketMetadata`, whose offset skips the framing.
+pub struct RtpPayloader {
+    pub(super) payload_format: RtpPayloadFormat,
+    pub(super) max_payload_size: usize,
+    pub(super) payload_type: u8,
+    pub(super) ssrc: u32,
+    pub(super) sequence_number: u16,
+    pub(super) time_base: ffi::AVRational,
+    pub(super) payloads: RtpPayloads,
+    pub(super) packet: Vec<u8>,
+}
+
+impl RtpPayloader {
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    fn payload<F>(&mut self, frame_data: &mut F) -> Result<(), CodecError>
+    where
+        F: EncodedPacketSink + EncodedPacketSource,
+    {
+        let access_unit = frame_data
+            .get_packet_data_buffer()
+            .ok_or(CodecError::MissingBuffer)?;
+        if access_unit.is_empty() {
+            return Ok(());
+        }
+
+        self.payloads.clear();
+        match self.payload_format {
+            RtpPayloadFormat::H264 => h264::packetize(access_unit, self.max_payload_size, &mut self.payloads)?,
+            RtpPayloadFormat::Vp8 => vp8::packetize(access_unit, self.max_payload_size, &mut self.payloads),
+            RtpPayloadFormat::Vp9 => vp9::packetize(access_unit, self.max_payload_size, &mut self.payloads)?,
+            RtpPayloadFormat::Av1 => av1::packetize(access_unit, self.max_payload_size, &mut self.payloads)?,
+        }
+
+        let keyframe = is_keyframe(self.payload_format, access_unit);
+        let frame_id = frame_data.get_frame_id();
+        let timestamp = rescale(frame_id, self.time_base, RTP_CLOCK_RATE) as u32;
+
+        frame_data.clear_packet_data();
+        frame_data.clear_packet_metadata();
+
+        let packets_count = self.payloads.len();
+        let mut written_bytes = 0;
+        for (index, payload) in self.payloads.iter().enumerate() {
+            let header = RtpHeader {
+                marker: index + 1 == packets_count,
+                payload_type: self.payload_type,
+                sequence_number: self.sequence_number,
+                timestamp,
+                ssrc: self.ssrc,
+            };
+            self.sequence_number = self.sequence_number.wrapping_add(1);
+
+            self.packet.clear();
+            self.packet.put_u16(0);
+            header.write(&mut self.packet);
+            self.packet.put_slice(payload);
+
+            let size = self.packet.len() - 2;
+            self.packet[..2].copy_from_slice(&(size as u16).to_be_bytes());
+
+            frame_data.write_packet_data(&self.packet)?;
+            frame_data.write_packet_metadata(PacketMetadata {
+                keyframe,
+                pts: frame_id,
+                dts: frame_id,
+                duration: 0,
+                offset: written_bytes + 2,
+                size,
+            });
+
+            written_bytes += self.packet.len();
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<F> FrameProcessor<F> for RtpPayloader
+where
+    F: EncodedPacketSink + EncodedPacketSource + Send + 'static,
+{
+    async fn process(&mut self, mut frame_data: F) -> Option<F> {
+        if let Err(error) = self.payload(&mut frame_data) {
+            log::warn!("Unable to payload frame {}: {}", frame_data.get_frame_id(), error);
+            let codec_name = self.payload_format.name();
+            frame_data.report_codec_error(match error {
+                CodecError::MissingBuffer => CodecErrorReport::missing_packet_buffer(codec_name),
+                error => CodecErrorReport::from_codec_error(CodecErrorKind::Payload, &error, codec_name),
+            });
+        }
+
+        Some(frame_data)
+    }
+}
@@ -0,0 +1,69 @@
+use crate::error::CodecError;
+
+use super::RtpPayloads;
+
+const EXTENDED_CONTROL_BITS: u8 = 0x80;
+const START_OF_PARTITION: u8 = 0x10;
+const PARTITION_INDEX: u8 = 0x07;
+
+const PICTURE_ID_PRESENT: u8 = 0x80;
+const TL0PICIDX_PRESENT: u8 = 0x40;
+const TID_PRESENT: u8 = 0x20;
+const KEYIDX_PRESENT: u8 = 0x10;
+const LONG_PICTURE_ID: u8 = 0x80;
+
+/// Splits the frame with a single byte payload descriptor, which only flags the start of the first partition.
+pub(super) fn packetize(frame: &[u8], max_payload_size: usize, payloads: &mut RtpPayloads) {
+    for (index, chunk) in frame.chunks(max_payload_size - 1).enumerate() {
+        let descriptor = if index == 0 { START_OF_PARTITION } else { 0 };
+
+        payloads.put_slice(&[descriptor]);
+        payloads.put_slice(chunk);
+        payloads.finish_payload();
+    }
+}
+
+pub(super) fn starts_frame(payload: &[u8]) -> bool {
+    payload
+        .first()
+        .is_some_and(|&descriptor| descriptor & START_OF_PARTITION != 0 && descriptor & PARTITION_INDEX == 0)
+}
+
+pub(super) fn depacketize(payloads: &[&[u8]], output: &mut Vec<u8>) -> Result<(), CodecError> {
+    for payload in payloads {
+        let descriptor_size = descriptor_size(payload)?;
+        output.extend_from_slice(&payload[descriptor_size..]);
+    }
+
+    Ok(())
+}
+
+fn descriptor_size(payload: &[u8]) -> Result<usize, CodecError> {
+    let truncated = CodecError::MalformedBitstream("truncated VP8 payload descriptor");
+
+    let descriptor = *payload.first().ok_or(truncated.clone())?;
+    let mut size = 1;
+
+    if descriptor & EXTENDED_CONTROL_BITS != 0 {
+        let extension = *payload.get(size).ok_or(truncated.clone())?;
+        size += 1;
+
+        if extension & PICTURE_ID_PRESENT != 0 {
+            let picture_id = *payload.get(size).ok_or(truncated.clone())?;
+            size += if picture_id & LONG_PICTURE_ID != 0 { 2 } else { 1 };
+        }
+        if extension & TL0PICIDX_PRESENT != 0 {
+            size += 1;
+        }
+        // TID and KEYIDX share the same byte
+        if extension & (TID_PRESENT | KEYIDX_PRESENT) != 0 {
+            size += 1;
+        }
+    }
+
+    if size > payload.len() {
+        return Err(truncated);
+    }
+
+    Ok(size)
+}
@@ -0,0 +1,172 @@
+use crate::{
+    bitstream::vpx::{superframe_frames, Vp9FrameHeader},
+    error::CodecError,
+};
+
+use super::RtpPayloads;
+
+const PICTURE_ID_PRESENT: u8 = 0x80;
+const INTER_PICTURE_PREDICTED: u8 = 0x40;
+const LAYER_INDICES_PRESENT: u8 = 0x20;
+const FLEXIBLE_MODE: u8 = 0x10;
+const START_OF_FRAME: u8 = 0x08;
+const END_OF_FRAME: u8 = 0x04;
+const SCALABILITY_STRUCTURE_PRESENT: u8 = 0x02;
+
+const LONG_PICTURE_ID: u8 = 0x80;
+const MORE_REFERENCE_INDICES: u8 = 0x01;
+
+/// Splits each frame of a superframe separately with a single byte payload descriptor, so that the frames share the
+/// RTP timestamp without their superframe index.
+pub(super) fn packetize(
+    access_unit: &[u8],
+    max_payload_size: usize,
+    payloads: &mut RtpPayloads,
+) -> Result<(), CodecError> {
+    for frame in superframe_frames(access_unit)? {
+        let header = Vp9FrameHeader::parse(frame)?;
+        let predicted = !header.keyframe && !header.intra_only;
+
+        let chunks = frame.chunks(max_payload_size - 1);
+        let chunks_count = chunks.len();
+        for (index, chunk) in chunks.enumerate() {
+            let mut descriptor = 0;
+            if predicted {
+                descriptor |= INTER_PICTURE_PREDICTED;
+            }
+            if index == 0 {
+                descriptor |= START_OF_FRAME;
+            }
+            if index + 1 == chunks_count {
+                descriptor |= END_OF_FRAME;
+            }
+
+            payloads.put_slice(&[descriptor]);
+            payloads.put_slice(chunk);
+            payloads.finish_payload();
+        }
+    }
+
+    Ok(())
+}
+
+pub(super) fn starts_frame(payload: &[u8]) -> bool {
+    payload
+        .first()
+        .is_some_and(|&descriptor| descriptor & START_OF_FRAME != 0)
+}
+
+/// Joins the frames of the payloads, adding back a superframe index when there are several of them.
+pub(super) fn depacketize(payloads: &[&[u8]], output: &mut Vec<u8>) -> Result<(), CodecError> {
+    let mut frame_sizes = Vec::new();
+    let mut frame_start = None;
+
+    for payload in payloads {
+        let descriptor_size = descriptor_size(payload)?;
+        let descriptor = payload[0];
+
+        if descriptor & START_OF_FRAME != 0 {
+            if frame_start.is_some() {
+                return Err(CodecError::MalformedBitstream("unterminated VP9 frame"));
+            }
+            frame_start = Some(output.len());
+        }
+
+        let Some(start) = frame_start else {
+            return Err(CodecError::MalformedBitstream("VP9 frame without start"));
+        };
+
+        output.extend_from_slice(&payload[descriptor_size..]);
+
+        if descriptor & END_OF_FRAME != 0 {
+            frame_sizes.push(output.len() - start);
+            frame_start = None;
+        }
+    }
+
+    if frame_start.is_some() {
+        return Err(CodecError::MalformedBitstream("unterminated VP9 frame"));
+    }
+
+    if frame_sizes.len() > 1 {
+        write_superframe_index(&frame_sizes, output)?;
+    }
+
+    Ok(())
+}
+
+fn write_superframe_index(frame_sizes: &[usize], output: &mut Vec<u8>) -> Result<(), CodecError> {
+    if frame_sizes.len() > 8 {
+        return Err(CodecError::MalformedBitstream("too many frames for a VP9 superframe"));
+    }
+
+    let largest_size = frame_sizes.iter().copied().max().unwrap_or(0);
+    let size_bytes = match largest_size {
+        0..=0xff => 1,
+        0x100..=0xffff => 2,
+        0x1_0000..=0xff_ffff => 3,
+        _ => 4,
+    };
+
+    let marker = 0xc0 | ((size_bytes as u8 - 1) << 3) | (frame_sizes.len() as u8 - 1);
+    output.push(marker);
+    for &frame_size in frame_sizes {
+        output.extend_from_slice(&(frame_size as u32).to_le_bytes()[..size_bytes]);
+    }
+    output.push(marker);
+
+    Ok(())
+}
+
+fn descriptor_size(payload: &[u8]) -> Result<usize, CodecError> {
+    let truncated = CodecError::MalformedBitstream("truncated VP9 payload descriptor");
+    let byte_at = |position: usize| payload.get(position).copied().ok_or(truncated.clone());
+
+    let descriptor = byte_at(0)?;
+    let mut size = 1;
+
+    if descriptor & PICTURE_ID_PRESENT != 0 {
+        size += if byte_at(size)? & LONG_PICTURE_ID != 0 { 2 } else { 1 };
+    }
+
+    let flexible = descriptor & FLEXIBLE_MODE != 0;
+    if descriptor & LAYER_INDICES_PRESENT != 0 {
+        // TL0PICIDX is only present in non-flexible mode
+        size += if flexible { 1 } else { 2 };
+    }
+
+    if flexible && descriptor & INTER_PICTURE_PREDICTED != 0 {
+        for _ in 0..3 {
+            let reference_index = byte_at(size)?;
+            size += 1;
+            if reference_index & MORE_REFERENCE_INDICES == 0 {
+                break;
+            }
+        }
+    }
+
+    if descriptor & SCALABILITY_STRUCTURE_PRESENT != 0 {
+        let structure = byte_at(size)?;
+        size += 1;
+
+        // Y flags the resolution of each spatial layer, G the picture group description
+        let spatial_layers = (structure >> 5) as usize + 1;
+        if structure & 0x10 != 0 {
+            size += 4 * spatial_layers;
+        }
+        if structure & 0x08 != 0 {
+            let pictures_count = byte_at(size)?;
+            size += 1;
+            for _ in 0..pictures_count {
+                let picture = byte_at(size)?;
+                size += 1 + ((picture >> 2) & 0x03) as usize; // P_DIFF of each reference
+            }
+        }
+    }
+
+    if size > payload.len() {
+        return Err(truncated);
+    }
+
+    Ok(size)
+}
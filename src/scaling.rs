@@ -1,13 +1,36 @@
-use crate::{builder::unwrap_mandatory, ffi};
-use rsmpeg::{avutil::AVFrame, swscale::SwsContext};
+use crate::{
+    builder::unwrap_mandatory,
+    error::CodecError,
+    ffi,
+    planes::{packed_size, plane_layouts, PlanesMut},
+};
+use rsmpeg::{avutil::AVFrame, error::RsmpegError, swscale::SwsContext};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameGeometry {
+    pub width: i32,
+    pub height: i32,
+    pub pixel_format: ffi::AVPixelFormat,
+}
+
+impl FrameGeometry {
+    pub fn of(avframe: &AVFrame) -> Self {
+        Self {
+            width: avframe.width,
+            height: avframe.height,
+            pixel_format: avframe.format,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct ScalerBuilder {
-    input_width: Option<i32>,
-    input_height: Option<i32>,
-    input_pixel_format: Option<ffi::AVPixelFormat>,
-    output_width: Option<i32>,
-    output_height: Option<i32>,
-    output_pixel_format: Option<ffi::AVPixelFormat>,
+    pub(crate) input_width: Option<i32>,
+    pub(crate) input_height: Option<i32>,
+    pub(crate) input_pixel_format: Option<ffi::AVPixelFormat>,
+    pub(crate) output_width: Option<i32>,
+    pub(crate) output_height: Option<i32>,
+    pub(crate) output_pixel_format: Option<ffi::AVPixelFormat>,
     scaling_flags: Option<u32>,
 }
 
@@ -38,77 +61,187 @@ impl ScalerBuilder {
     builder_set!(output_pixel_format, ffi::AVPixelFormat);
     builder_set!(scaling_flags, u32);
 
-    pub fn build(self) -> Scaler {
-        let input_width = unwrap_mandatory(self.input_width);
-        let input_height = unwrap_mandatory(self.input_height);
-        let input_pixel_format = unwrap_mandatory(self.input_pixel_format);
+    pub fn input_geometry(self, geometry: FrameGeometry) -> Self {
+        self.input_width(geometry.width)
+            .input_height(geometry.height)
+            .input_pixel_format(geometry.pixel_format)
+    }
+
+    /// Builds the scaler. Output size and pixel format default to the input ones.
+    ///
+    /// When input and output geometries match, no scaling context is created and the scaled frame is the input frame
+    /// itself.
+    pub fn build(self) -> Result<Scaler, CodecError> {
+        let builder = self.clone();
+
+        let input_geometry = FrameGeometry {
+            width: unwrap_mandatory(self.input_width, "input_width")?,
+            height: unwrap_mandatory(self.input_height, "input_height")?,
+            pixel_format: unwrap_mandatory(self.input_pixel_format, "input_pixel_format")?,
+        };
+
+        let output_geometry = FrameGeometry {
+            width: self.output_width.unwrap_or(input_geometry.width),
+            height: self.output_height.unwrap_or(input_geometry.height),
+            pixel_format: self
+                .output_pixel_format
+                .unwrap_or(input_geometry.pixel_format),
+        };
+
+        let input_frame = allocate_frame(input_geometry)?;
 
-        let output_width = self.output_width.unwrap_or(input_width);
-        let output_height = self.output_height.unwrap_or(input_height);
-        let output_pixel_format = unwrap_mandatory(self.output_pixel_format);
+        if input_geometry == output_geometry {
+            return Ok(Scaler {
+                builder,
+                sws_context: None,
+                input_frame,
+                scaled_frame: None,
+            });
+        }
 
         let scaling_flags = self.scaling_flags.unwrap_or(ffi::SWS_BILINEAR);
 
         let sws_context = {
             SwsContext::get_context(
-                input_width,
-                input_height,
-                input_pixel_format,
-                output_width,
-                output_height,
-                output_pixel_format,
+                input_geometry.width,
+                input_geometry.height,
+                input_geometry.pixel_format,
+                output_geometry.width,
+                output_geometry.height,
+                output_geometry.pixel_format,
                 scaling_flags,
             )
-            .unwrap()
-        };
-
-        let input_avframe = {
-            let mut avframe = AVFrame::new();
-            avframe.set_format(input_pixel_format);
-            avframe.set_width(input_width);
-            avframe.set_height(input_height);
-            avframe.alloc_buffer().unwrap();
-            avframe
-        };
-
-        let output_avframe = {
-            let mut avframe = AVFrame::new();
-            avframe.set_format(output_pixel_format);
-            avframe.set_width(output_width);
-            avframe.set_height(output_height);
-            avframe.alloc_buffer().unwrap();
-            avframe
+            .ok_or(CodecError::ScalerContextFailed)?
         };
 
-        Scaler {
-            input_frame: input_avframe,
-            scaled_frame: output_avframe,
-            sws_context,
-        }
+        Ok(Scaler {
+            builder,
+            sws_context: Some(sws_context),
+            input_frame,
+            scaled_frame: Some(allocate_frame(output_geometry)?),
+        })
     }
 }
 
+fn allocate_frame(geometry: FrameGeometry) -> Result<AVFrame, CodecError> {
+    let mut avframe = AVFrame::new();
+    avframe.set_format(geometry.pixel_format);
+    avframe.set_width(geometry.width);
+    avframe.set_height(geometry.height);
+    avframe
+        .alloc_buffer()
+        .map_err(CodecError::frame_allocation_failed)?;
+    Ok(avframe)
+}
+
 pub struct Scaler {
-    sws_context: SwsContext,
+    builder: ScalerBuilder,
+    sws_context: Option<SwsContext>,
     input_frame: AVFrame,
-    scaled_frame: AVFrame,
+    scaled_frame: Option<AVFrame>,
 }
 impl Scaler {
-    pub fn scale(&mut self) {
+    pub fn scale(&mut self) -> Result<(), RsmpegError> {
+        let (sws_context, scaled_frame) = match (&mut self.sws_context, &mut self.scaled_frame) {
+            (Some(sws_context), Some(scaled_frame)) => (sws_context, scaled_frame),
+            _ => return Ok(()),
+        };
+
         let input_frame = &self.input_frame;
-        let scaled_frame = &mut self.scaled_frame;
+        sws_context.scale_frame(input_frame, 0, input_frame.height, scaled_frame)
+    }
+
+    pub fn scale_input(&mut self, input_frame: &AVFrame) -> Result<(), RsmpegError> {
+        let (sws_context, scaled_frame) = match (&mut self.sws_context, &mut self.scaled_frame) {
+            (Some(sws_context), Some(scaled_frame)) => (sws_context, scaled_frame),
+            _ => {
+                // Without a scaling context the input frame is referenced as it is
+                let result = unsafe {
+                    ffi::av_frame_unref(self.input_frame.as_mut_ptr());
+                    ffi::av_frame_ref(self.input_frame.as_mut_ptr(), input_frame.as_ptr())
+                };
+
+                return match result {
+                    0 => Ok(()),
+                    code => Err(RsmpegError::AVError(code)),
+                };
+            }
+        };
+
+        sws_context.scale_frame(input_frame, 0, input_frame.height, scaled_frame)
+    }
+
+    /// Scales a tightly packed picture laid out as the input geometry, reading it in place instead of copying it into
+    /// the input frame first. Without a scaling context the picture is copied into the input frame, which is the frame
+    /// handed to the encoder, so that the copy is the only one either way.
+    pub fn scale_packed(&mut self, source: &[u8]) -> Result<(), CodecError> {
+        let (sws_context, scaled_frame) = match (&mut self.sws_context, &mut self.scaled_frame) {
+            (Some(sws_context), Some(scaled_frame)) => (sws_context, scaled_frame),
+            _ => return PlanesMut::new(&mut self.input_frame)?.copy_from_packed(source),
+        };
+
+        let input_geometry = FrameGeometry::of(&self.input_frame);
+        let layouts = plane_layouts(input_geometry.pixel_format, input_geometry.width, input_geometry.height)?;
+
+        let expected_size = packed_size(&layouts);
+        if source.len() != expected_size {
+            return Err(CodecError::BufferSizeMismatch {
+                expected: expected_size,
+                actual: source.len(),
+            });
+        }
+
+        let mut planes = [std::ptr::null(); 4];
+        let mut strides = [0; 4];
+        let mut offset = 0;
+        for (index, layout) in layouts.iter().enumerate() {
+            planes[index] = source[offset..].as_ptr();
+            strides[index] = layout.row_bytes as i32;
+            offset += layout.row_bytes * layout.height;
+        }
+
+        let result = unsafe {
+            ffi::sws_scale(
+                sws_context.as_mut_ptr(),
+                planes.as_ptr(),
+                strides.as_ptr(),
+                0,
+                input_geometry.height,
+                scaled_frame.data.as_ptr(),
+                scaled_frame.linesize.as_ptr(),
+            )
+        };
+
+        if result < 0 {
+            return Err(CodecError::scaling_failed(result));
+        }
 
-        self.sws_context
-            .scale_frame(input_frame, 0, input_frame.height, scaled_frame)
-            .unwrap();
+        Ok(())
     }
 
-    pub fn scale_input(&mut self, input_frame: &AVFrame) {
-        let scaled_frame = &mut self.scaled_frame;
+    /// Replaces the scaling context and the frames to match a new input geometry.
+    ///
+    /// The output size follows the input one unless it has been set explicitly when building the scaler.
+    pub fn rebuild(&mut self, input_geometry: FrameGeometry) -> Result<(), CodecError> {
+        *self = self
+            .builder
+            .clone()
+            .input_geometry(input_geometry)
+            .build()?;
+
+        Ok(())
+    }
+
+    pub fn is_passthrough(&self) -> bool {
+        self.sws_context.is_none()
+    }
+
+    pub fn input_geometry(&self) -> FrameGeometry {
+        FrameGeometry::of(&self.input_frame)
+    }
 
-        self.sws_context
-            .scale_frame(input_frame, 0, input_frame.height, scaled_frame)
-            .unwrap();
+    pub fn output_geometry(&self) -> FrameGeometry {
+        FrameGeometry::of(self.scaled_frame())
     }
 
     pub fn input_frame(&self) -> &AVFrame {
@@ -116,7 +249,7 @@ impl Scaler {
     }
 
     pub fn scaled_frame(&self) -> &AVFrame {
-        &self.scaled_frame
+        self.scaled_frame.as_ref().unwrap_or(&self.input_frame)
     }
 
     pub fn input_frame_mut(&mut self) -> &mut AVFrame {
@@ -124,6 +257,6 @@ impl Scaler {
     }
 
     pub fn scaled_frame_mut(&mut self) -> &mut AVFrame {
-        &mut self.scaled_frame
+        self.scaled_frame.as_mut().unwrap_or(&mut self.input_frame)
     }
 }
@@ -0,0 +1,153 @@
+use std::time::Instant;
+
+use crate::ffi;
+
+pub(crate) const DEFAULT_TIME_BASE: ffi::AVRational = ffi::AVRational { num: 1, den: 60 * 1000 };
+pub(crate) const DEFAULT_FRAME_RATE: ffi::AVRational = ffi::AVRational { num: 60, den: 1 };
+
+const MICROSECONDS: ffi::AVRational = ffi::AVRational {
+    num: 1,
+    den: 1000 * 1000,
+};
+const MILLISECONDS: ffi::AVRational = ffi::AVRational { num: 1, den: 1000 };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampMode {
+    /// The pipeline frame id is used as presentation timestamp, without any conversion.
+    #[default]
+    FrameId,
+
+    /// Presentation timestamps are taken from the wall-clock capture time of frames, as given by
+    /// `CodecFrame::capture_instant`, measured from the first encoded frame. Frames which do not provide it are
+    /// stamped with the time at which they reach the encoder, so that scaling and queueing delays end up in their
+    /// timestamps. On both the encoder and the decoder side, frame ids are expressed as milliseconds elapsed since
+    /// that first frame.
+    WallClock,
+}
+
+#[derive(Clone)]
+pub(crate) struct Timestamper {
+    mode: TimestampMode,
+    time_base: ffi::AVRational,
+    epoch: Option<Instant>,
+    last_pts: Option<i64>,
+}
+
+impl Timestamper {
+    pub fn new(mode: TimestampMode, time_base: ffi::AVRational) -> Self {
+        Self {
+            mode,
+            time_base,
+            epoch: None,
+            last_pts: None,
+        }
+    }
+
+    /// Computes the pts of a frame that is about to be sent to the encoder.
+    pub fn frame_pts(&mut self, frame_id: i64, capture_instant: Option<Instant>) -> i64 {
+        match self.mode {
+            TimestampMode::FrameId => frame_id,
+            TimestampMode::WallClock => {
+                let instant = capture_instant.unwrap_or_else(Instant::now);
+                let epoch = *self.epoch.get_or_insert(instant);
+                let elapsed = instant.saturating_duration_since(epoch).as_micros() as i64;
+                let mut pts = rescale(elapsed, MICROSECONDS, self.time_base);
+
+                // Encoders reject non strictly increasing timestamps
+                if let Some(last_pts) = self.last_pts {
+                    pts = pts.max(last_pts + 1);
+                }
+                self.last_pts = Some(pts);
+
+                pts
+            }
+        }
+    }
+
+    /// Converts the pts of an encoded packet or a decoded frame to a pipeline frame id.
+    pub fn frame_id(&self, pts: i64) -> i64 {
+        match self.mode {
+            TimestampMode::FrameId => pts,
+            TimestampMode::WallClock => rescale(pts, self.time_base, MILLISECONDS),
+        }
+    }
+
+    /// Converts a pipeline frame id to the pts of the packets sent to the decoder.
+    pub fn packet_pts(&self, frame_id: i64) -> i64 {
+        match self.mode {
+            TimestampMode::FrameId => frame_id,
+            TimestampMode::WallClock => rescale(frame_id, MILLISECONDS, self.time_base),
+        }
+    }
+}
+
+pub(crate) fn rescale(value: i64, from: ffi::AVRational, to: ffi::AVRational) -> i64 {
+    if value == ffi::AV_NOPTS_VALUE {
+        return value;
+    }
+
+    unsafe { ffi::av_rescale_q(value, from, to) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const TIME_BASE: ffi::AVRational = ffi::AVRational { num: 1, den: 90000 };
+
+    #[test]
+    fn rescales_between_time_bases() {
+        assert_eq!(rescale(1500, MILLISECONDS, TIME_BASE), 135000);
+        assert_eq!(rescale(135000, TIME_BASE, MILLISECONDS), 1500);
+        assert_eq!(rescale(-20, MILLISECONDS, TIME_BASE), -1800);
+    }
+
+    #[test]
+    fn keeps_missing_timestamps() {
+        assert_eq!(
+            rescale(ffi::AV_NOPTS_VALUE, MILLISECONDS, TIME_BASE),
+            ffi::AV_NOPTS_VALUE
+        );
+    }
+
+    #[test]
+    fn passes_frame_ids_through() {
+        let mut timestamper = Timestamper::new(TimestampMode::FrameId, TIME_BASE);
+
+        assert_eq!(timestamper.frame_pts(42, Some(Instant::now())), 42);
+        assert_eq!(timestamper.frame_id(42), 42);
+        assert_eq!(timestamper.packet_pts(42), 42);
+    }
+
+    #[test]
+    fn stamps_capture_instants_from_the_first_frame() {
+        let mut timestamper = Timestamper::new(TimestampMode::WallClock, TIME_BASE);
+        let epoch = Instant::now();
+
+        assert_eq!(timestamper.frame_pts(1000, Some(epoch)), 0);
+        assert_eq!(timestamper.frame_pts(0, Some(epoch + Duration::from_millis(40))), 3600);
+        assert_eq!(timestamper.frame_pts(0, Some(epoch + Duration::from_millis(100))), 9000);
+    }
+
+    #[test]
+    fn keeps_wall_clock_timestamps_strictly_increasing() {
+        let mut timestamper = Timestamper::new(TimestampMode::WallClock, TIME_BASE);
+        let epoch = Instant::now() + Duration::from_millis(10);
+
+        assert_eq!(timestamper.frame_pts(0, Some(epoch)), 0);
+        assert_eq!(timestamper.frame_pts(0, Some(epoch)), 1);
+        assert_eq!(timestamper.frame_pts(0, Some(epoch - Duration::from_millis(5))), 2);
+        assert_eq!(timestamper.frame_pts(0, Some(epoch + Duration::from_millis(1))), 90);
+    }
+
+    #[test]
+    fn converts_wall_clock_timestamps_to_milliseconds() {
+        let timestamper = Timestamper::new(TimestampMode::WallClock, TIME_BASE);
+
+        assert_eq!(timestamper.frame_id(9000), 100);
+        assert_eq!(timestamper.packet_pts(100), 9000);
+        assert_eq!(timestamper.frame_id(timestamper.packet_pts(1234)), 1234);
+    }
+}